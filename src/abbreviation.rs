@@ -0,0 +1,177 @@
+use crate::shell::Osh;
+use crate::werror;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+
+use console::style;
+
+/// Abbreviations, shared with the key handler of the line editor
+pub type Abbreviations = Arc<RwLock<HashMap<String, String>>>;
+
+/// Find the abbreviation ending at `pos` in `line`, if it is in command position. Return the
+/// position where the abbreviation starts and its expansion.
+pub fn find_abbreviation(
+    line: &str,
+    pos: usize,
+    abbreviations: &HashMap<String, String>,
+) -> Option<(usize, String)> {
+    // The cursor must be at the end of the word
+    if line[pos..]
+        .chars()
+        .next()
+        .is_some_and(|c| !c.is_whitespace())
+    {
+        return None;
+    }
+    let before = &line[..pos];
+    let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let (prefix, word) = before.split_at(start);
+
+    // Like aliases, abbreviations only apply to commands
+    let prefix = prefix.trim_end();
+    if !prefix.is_empty() && !prefix.ends_with(['|', ';', '&']) {
+        return None;
+    }
+    abbreviations
+        .get(word)
+        .map(|expansion| (start, expansion.clone()))
+}
+
+/// Expand the abbreviation the line accepted ends with, when `AcceptHandler` could not expand
+/// it in the line editor.
+pub fn expand_abbreviations(line: &str, abbreviations: &Abbreviations) -> String {
+    let abbreviations = abbreviations.read().unwrap();
+    match find_abbreviation(line, line.len(), &abbreviations) {
+        Some((start, expansion)) => format!("{}{}", &line[..start], expansion),
+        None => line.to_string(),
+    }
+}
+
+/// Key handler expanding abbreviations when a space is typed right after them.
+///
+/// The expansion itself is offered by the completer when `requested` is set, since it replaces
+/// the abbreviation and moves the cursor after the expansion, unlike `Cmd::Replace`. Completing
+/// with Tab leaves abbreviations alone.
+pub struct AbbreviationHandler {
+    pub abbreviations: Abbreviations,
+    pub requested: Arc<AtomicBool>,
+}
+
+impl ConditionalEventHandler for AbbreviationHandler {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let abbreviations = self.abbreviations.read().ok()?;
+        let (start, expansion) = find_abbreviation(ctx.line(), ctx.pos(), &abbreviations)?;
+        // The completer only replaces words with longer candidates
+        if expansion.len() < ctx.pos() - start {
+            return None;
+        }
+        self.requested.store(true, Ordering::Relaxed);
+        Some(Cmd::Complete)
+    }
+}
+
+/// Push the Enter key back to the terminal, so the line editor reads it next. Fails when the
+/// kernel does not allow it, or when input typed ahead would be read first.
+fn push_back_enter() -> bool {
+    let mut pending: libc::c_int = 0;
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::FIONREAD, &mut pending) } != 0 || pending > 0
+    {
+        return false;
+    }
+    let enter = b'\r';
+    unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCSTI, &enter) == 0 }
+}
+
+/// Key handler for Enter, expanding the abbreviation the line ends with before accepting it, so
+/// the actual command is displayed. The line is replaced, then accepted when the Enter key
+/// pushed back is read. Lines are accepted as they are when the key cannot be pushed back, and
+/// expanded by `expand_abbreviations`.
+pub struct AcceptHandler {
+    pub abbreviations: Abbreviations,
+    /// Set when the line was just expanded, so it is accepted rather than expanded again
+    pub expanded: AtomicBool,
+}
+
+impl ConditionalEventHandler for AcceptHandler {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if self.expanded.swap(false, Ordering::Relaxed) {
+            return None;
+        }
+        let line = ctx.line();
+        let abbreviations = self.abbreviations.read().ok()?;
+        let (start, expansion) = find_abbreviation(line, line.len(), &abbreviations)?;
+        if !push_back_enter() {
+            return None;
+        }
+        self.expanded.store(true, Ordering::Relaxed);
+        let line = format!("{}{}", &line[..start], expansion);
+        Some(Cmd::Replace(Movement::WholeBuffer, Some(line)))
+    }
+}
+
+pub trait Abbreviation {
+    fn abbr(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl Abbreviation for Osh {
+    /// Implementation of the `abbr` builtin. Abbreviations are replaced with their expansion
+    /// while typing, when followed by a space or Enter, so the actual command is displayed and
+    /// saved in the history.
+    ///
+    /// - `abbr` or `abbr -l`: list abbreviations
+    /// - `abbr [-a] NAME EXPANSION...`: add an abbreviation
+    /// - `abbr -e NAME`: erase an abbreviation
+    fn abbr(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut abbreviations = self.abbreviations.write().unwrap();
+        match args.split_first() {
+            None => (),
+            Some((flag, _)) if flag == "-l" || flag == "--list" => (),
+            Some((flag, names)) if flag == "-e" || flag == "--erase" => {
+                if names.is_empty() {
+                    werror!("abbr: {}: no abbreviation provided", flag);
+                    return 2;
+                }
+                let mut status = 0;
+                for name in names {
+                    if abbreviations.remove(name).is_none() {
+                        werror!("abbr: {}: not found", name);
+                        status = 1;
+                    }
+                }
+                return status;
+            }
+            Some((flag, rest)) => {
+                let definition = if flag == "-a" || flag == "--add" {
+                    rest
+                } else {
+                    args
+                };
+                return match definition.split_first() {
+                    Some((name, expansion)) if !expansion.is_empty() => {
+                        abbreviations.insert(name.clone(), expansion.join(" "));
+                        0
+                    }
+                    _ => {
+                        werror!("abbr: usage: abbr [-a] NAME EXPANSION...");
+                        2
+                    }
+                };
+            }
+        }
+
+        let mut names = abbreviations.keys().collect::<Vec<&String>>();
+        names.sort();
+        for name in names {
+            if let Err(e) = writeln!(output, "{}: {}", name, abbreviations[name]) {
+                werror!("abbr: write error: {}", e);
+                return 1;
+            }
+        }
+        0
+    }
+}
@@ -1,20 +1,397 @@
+use crate::config::{save_alias, ConfigFile};
 use crate::shell::Osh;
+use crate::werror;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+
+use console::style;
+use skim::prelude::*;
+
+/// Maximum number of aliases expanded within each other. Loops are detected before this limit
+/// is reached, it only bounds the work done for very long chains of aliases
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// Words an alias is replaced with
+pub struct AliasExpansion {
+    pub words: Vec<String>,
+    /// Set when the replacement ends with a blank, in which case the next word is expanded as
+    /// if it were in command position
+    pub trailing_blank: bool,
+    /// Number of arguments used to fill the placeholders of the alias
+    pub consumed: usize,
+}
+
+/// Let the user type the value of a `{?TEXT}` placeholder, with `TEXT` as prompt.
+fn ask(prompt: &str) -> Option<String> {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return None;
+    }
+
+    let prompt = format!("{}: ", prompt);
+    let options = SkimOptionsBuilder::default()
+        .height(Some("30%"))
+        .reverse(true)
+        .prompt(Some(&prompt))
+        .build()
+        .unwrap();
+    let items = SkimItemReader::default().of_bufread(Cursor::new(String::new()));
+
+    let output = Skim::run_with(&options, Some(items))?;
+    if output.is_abort {
+        return None;
+    }
+    Some(output.query)
+}
+
+/// Fill the placeholders of the alias `name`, whose replacement is `template`:
+///
+/// - `{N}`: the Nth argument following the alias
+/// - `{@}`: every argument following the alias
+/// - `{?TEXT}`: what the user types at the prompt `TEXT`
+///
+/// Return the replacement and the number of arguments used, the other ones are kept after the
+/// replacement like for other aliases.
+fn fill_placeholders(
+    name: &str,
+    template: &str,
+    args: &[String],
+) -> Result<(String, usize), String> {
+    let mut result = String::new();
+    let mut consumed = 0;
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let placeholder = &rest[start + 1..end];
+        let value = match placeholder.parse::<usize>() {
+            Ok(n) if n > 0 => {
+                let arg = args
+                    .get(n - 1)
+                    .ok_or_else(|| format!("alias {}: missing argument {}", name, n))?;
+                consumed = consumed.max(n);
+                shell_words::quote(arg).into_owned()
+            }
+            _ if placeholder == "@" => {
+                consumed = args.len();
+                shell_words::join(args)
+            }
+            _ if placeholder.starts_with('?') => {
+                let value = ask(&placeholder[1..]).ok_or_else(|| {
+                    format!("alias {}: {}: no value provided", name, &placeholder[1..])
+                })?;
+                shell_words::quote(&value).into_owned()
+            }
+            // Other braces are kept as is, such as the ones of `${name}`
+            _ => {
+                result.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok((result, consumed))
+}
+
+/// Kinds of aliases, which differ in where they are expanded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AliasKind {
+    /// Expanded in command position only, such as `alias ll='ls -l'`
+    Command,
+    /// Expanded anywhere in the command line, such as `alias -g G='| grep'`
+    Global,
+    /// Run files with a given extension used as command with a program, such as
+    /// `alias -s md=glow`
+    Suffix,
+}
+
+impl AliasKind {
+    pub const ALL: [AliasKind; 3] = [AliasKind::Command, AliasKind::Global, AliasKind::Suffix];
+
+    /// Key of the aliases of this kind in configuration files of version 1
+    pub fn section(self) -> &'static str {
+        match self {
+            AliasKind::Command => "aliases",
+            AliasKind::Global => "global_aliases",
+            AliasKind::Suffix => "suffix_aliases",
+        }
+    }
+
+    /// Key of the aliases of this kind under `aliases` in configuration files
+    pub fn key(self) -> &'static str {
+        match self {
+            AliasKind::Command => "command",
+            AliasKind::Global => "global",
+            AliasKind::Suffix => "suffix",
+        }
+    }
+
+    /// Aliases of this kind defined in `config`
+    pub fn config_aliases(self, config: &mut ConfigFile) -> &mut HashMap<String, String> {
+        match self {
+            AliasKind::Command => &mut config.aliases.command,
+            AliasKind::Global => &mut config.aliases.global,
+            AliasKind::Suffix => &mut config.aliases.suffix,
+        }
+    }
+}
 
 pub trait Alias {
+    fn alias_table(&mut self, kind: AliasKind) -> &mut HashMap<String, String>;
     fn lookup_aliases(&self, value: &str) -> Option<String>;
-    fn list_aliases(&self);
+    fn expand_alias(&self, word: &str, command_position: bool) -> Option<String>;
+    fn expand_alias_recursively(
+        &self,
+        word: &str,
+        command_position: bool,
+        args: &[String],
+    ) -> Result<Option<AliasExpansion>, String>;
+    fn expand_nested_alias(
+        &self,
+        word: &str,
+        command_position: bool,
+        args: &[String],
+        chain: &mut Vec<String>,
+    ) -> Result<Option<AliasExpansion>, String>;
+    fn list_aliases(&mut self, kind: AliasKind, output: &mut dyn Write) -> u32;
+    fn alias(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn unalias(&mut self, args: &[String]) -> u32;
+    fn save_alias(&mut self, kind: AliasKind, name: &str, value: Option<&str>) -> u32;
+}
+
+/// Parse the options shared by `alias` and `unalias`: `-g`, `-s` and `--save`.
+fn parse_alias_options(args: &[String]) -> (AliasKind, bool, &[String]) {
+    let mut kind = AliasKind::Command;
+    let mut save = false;
+    let mut args = args;
+    while let Some((flag, rest)) = args.split_first() {
+        match flag.as_str() {
+            "-g" => kind = AliasKind::Global,
+            "-s" => kind = AliasKind::Suffix,
+            "--save" => save = true,
+            "--" => return (kind, save, rest),
+            _ => break,
+        }
+        args = rest;
+    }
+    (kind, save, args)
 }
 
 impl Alias for Osh {
+    /// Aliases of the kind supplied.
+    fn alias_table(&mut self, kind: AliasKind) -> &mut HashMap<String, String> {
+        match kind {
+            AliasKind::Command => &mut self.aliases,
+            AliasKind::Global => &mut self.global_aliases,
+            AliasKind::Suffix => &mut self.suffix_aliases,
+        }
+    }
+
     /// Replace the `command` with an alias if available.
     fn lookup_aliases(&self, value: &str) -> Option<String> {
-        self.aliases.get(value).map(|s| s.to_string())
+        self.aliases
+            .get(value)
+            .or_else(|| self.global_aliases.get(value))
+            .map(|s| s.to_string())
+    }
+
+    /// Find the replacement of `word`. Aliases only apply in `command_position`, at the
+    /// start of a command, while global aliases apply anywhere. Files whose extension has a
+    /// suffix alias are run with the program associated to the extension.
+    fn expand_alias(&self, word: &str, command_position: bool) -> Option<String> {
+        if !command_position {
+            return self.global_aliases.get(word).cloned();
+        }
+        if let Some(alias) = self.lookup_aliases(word) {
+            return Some(alias);
+        }
+
+        let (_, extension) = word.rsplit_once('.')?;
+        self.suffix_aliases
+            .get(extension)
+            .map(|program| format!("{} {}", program, shell_words::quote(word)))
+    }
+
+    /// Expand the alias of `word`, then the aliases found in its replacement, like
+    /// `expand_alias` does. The placeholders of the alias are filled with `args`, the words
+    /// following it.
+    fn expand_alias_recursively(
+        &self,
+        word: &str,
+        command_position: bool,
+        args: &[String],
+    ) -> Result<Option<AliasExpansion>, String> {
+        self.expand_nested_alias(word, command_position, args, &mut Vec::new())
+    }
+
+    /// Expand `word` within the aliases of `chain`, the outermost first.
+    fn expand_nested_alias(
+        &self,
+        word: &str,
+        command_position: bool,
+        args: &[String],
+        chain: &mut Vec<String>,
+    ) -> Result<Option<AliasExpansion>, String> {
+        // An alias may use the command it replaces, such as `alias ls='ls -F'`
+        if chain.last().map(String::as_str) == Some(word) {
+            return Ok(None);
+        }
+        if chain.iter().any(|name| name == word) {
+            return Err(format!("alias loop: {} -> {}", chain.join(" -> "), word));
+        }
+
+        let replacement = match self.expand_alias(word, command_position) {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        if chain.len() >= MAX_ALIAS_DEPTH {
+            return Err(format!(
+                "alias {}: more than {} nested aliases",
+                chain[0], MAX_ALIAS_DEPTH
+            ));
+        }
+        let (replacement, consumed) = fill_placeholders(word, &replacement, args)?;
+        let parts =
+            shell_words::split(&replacement).map_err(|e| format!("alias {}: {}", word, e))?;
+
+        chain.push(word.to_string());
+        let mut words = Vec::new();
+        let mut trailing_blank = false;
+        for (i, part) in parts.iter().enumerate() {
+            let command_position =
+                (i == 0 && command_position) || (i > 0 && parts[i - 1] == "|") || trailing_blank;
+            // Only the words following the outermost alias are its arguments
+            match self.expand_nested_alias(part, command_position, &[], chain)? {
+                Some(expansion) => {
+                    words.extend(expansion.words);
+                    trailing_blank = expansion.trailing_blank;
+                }
+                None => {
+                    words.push(part.clone());
+                    trailing_blank = false;
+                }
+            }
+        }
+        chain.pop();
+
+        // The word following an alias ending with a blank is checked for aliases too
+        let trailing_blank = trailing_blank || replacement.ends_with([' ', '\t']);
+        Ok(Some(AliasExpansion {
+            words,
+            trailing_blank,
+            consumed,
+        }))
     }
 
     // TODO: List aliases for better readability
-    fn list_aliases(&self) {
-        for (key, value) in self.aliases.iter() {
-            println!("{}: {}", key, value);
+    fn list_aliases(&mut self, kind: AliasKind, output: &mut dyn Write) -> u32 {
+        for (key, value) in self.alias_table(kind).iter() {
+            if let Err(e) = writeln!(output, "{}: {}", key, value) {
+                werror!("alias: write error: {}", e);
+                return 1;
+            }
         }
+        0
+    }
+
+    /// Implementation of the `alias` builtin: `alias NAME COMMAND...` or `alias NAME=COMMAND`
+    /// registers a new alias, `alias` displays the available aliases.
+    ///
+    /// - `-g`: define or list global aliases, expanded anywhere in the command line
+    /// - `-s`: define or list suffix aliases, such as `alias -s md=glow` to run `glow FILE.md`
+    ///   when `FILE.md` is used as a command
+    /// - `--save`: write the alias to the configuration file as well. `alias --save NAME`
+    ///   saves an alias defined earlier
+    fn alias(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let (kind, save, args) = parse_alias_options(args);
+
+        // Register a new alias
+        let mut args = args.iter();
+        let new_alias = match args.next() {
+            Some(v) => v.clone(),
+            None if save => {
+                werror!("alias: --save: no alias provided");
+                return 1;
+            }
+            None => return self.list_aliases(kind, output),
+        };
+
+        // Build the command by parsing the rest of the command provided
+        let rest = args.cloned().collect::<Vec<String>>();
+        let (new_alias, aliased) = match new_alias.split_once('=') {
+            Some((name, command)) if !name.is_empty() => (
+                name.to_string(),
+                std::iter::once(command.to_string())
+                    .chain(rest)
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            _ if save && rest.is_empty() => match self.alias_table(kind).get(&new_alias) {
+                Some(command) => (new_alias, command.clone()),
+                None => {
+                    werror!("alias: {}: not found", new_alias);
+                    return 1;
+                }
+            },
+            _ => (new_alias, rest.join(" ")),
+        };
+
+        self.alias_table(kind)
+            .insert(new_alias.clone(), aliased.clone());
+        if save {
+            return self.save_alias(kind, &new_alias, Some(&aliased));
+        }
+        0
+    }
+
+    /// Implementation of the `unalias` builtin: `unalias NAME` removes an alias, `-g` and `-s`
+    /// a global or suffix alias. With `--save`, it is removed from the configuration file as
+    /// well.
+    fn unalias(&mut self, args: &[String]) -> u32 {
+        let (kind, save, args) = parse_alias_options(args);
+
+        // Fetch the name of the alias to remove
+        let request = match args.first() {
+            Some(v) => v,
+            None => {
+                werror!("No alias provided");
+                return 1;
+            }
+        };
+
+        let removed = self.alias_table(kind).remove(request).is_some();
+        if save && (removed || kind.config_aliases(&mut self.config).contains_key(request)) {
+            return self.save_alias(kind, request, None);
+        }
+        if !removed {
+            werror!("{} is not an alias", request);
+            return 1;
+        }
+        0
+    }
+
+    /// Write the alias `name`, or its removal if `value` is not set, to the configuration
+    /// file.
+    fn save_alias(&mut self, kind: AliasKind, name: &str, value: Option<&str>) -> u32 {
+        if let Err(e) = save_alias(kind, name, value) {
+            werror!("alias: {}", e);
+            return 1;
+        }
+
+        // Keep track of the content of the file, so `reload` knows the alias comes from there
+        let aliases = kind.config_aliases(&mut self.config);
+        match value {
+            Some(value) => aliases.insert(name.into(), value.into()),
+            None => aliases.remove(name),
+        };
+        0
     }
 }
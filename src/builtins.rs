@@ -0,0 +1,622 @@
+use crate::abbreviation::Abbreviation;
+use crate::alias::Alias;
+use crate::change_directory::ChangeDirectory;
+use crate::config::{check_config, config_layers, user_config_path, ConfigFile};
+use crate::directory_config::DirectoryConfiguration;
+use crate::directory_stack::DirectoryStack;
+use crate::frecency::Frecency;
+use crate::input::Input;
+use crate::jobs::Jobs;
+use crate::parameters::Parameters;
+use crate::print::Print;
+use crate::resolution::Resolve;
+use crate::resources::Resources;
+use crate::shell::Osh;
+use crate::trap::Trap;
+use crate::{werror, winfo};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, stdout, Cursor, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{ChildStdout, Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use console::style;
+
+/// Standard input of a builtin.
+pub enum InputStream {
+    /// Standard input of the shell
+    Inherited,
+    /// Output of the previous external command of the pipeline
+    Pipe(ChildStdout),
+    /// Output of the previous builtin of the pipeline
+    Buffer(Cursor<Vec<u8>>),
+    /// File redirected with `<`
+    File(File),
+}
+
+impl InputStream {
+    fn raw_fd(&self) -> Option<RawFd> {
+        match self {
+            InputStream::Inherited => Some(libc::STDIN_FILENO),
+            InputStream::Pipe(pipe) => Some(pipe.as_raw_fd()),
+            InputStream::Buffer(_) => None,
+            InputStream::File(file) => Some(file.as_raw_fd()),
+        }
+    }
+
+    /// Open the input as a file of its own, so commands run by a builtin can read it. The
+    /// output of a previous builtin is copied to an anonymous file. Return `None` for the
+    /// standard input of the shell.
+    pub fn open_file(&mut self) -> io::Result<Option<File>> {
+        let fd = match self {
+            InputStream::Inherited => return Ok(None),
+            InputStream::Pipe(pipe) => unsafe { libc::dup(pipe.as_raw_fd()) },
+            InputStream::File(file) => unsafe { libc::dup(file.as_raw_fd()) },
+            InputStream::Buffer(buffer) => {
+                let name = b"osh-input\0";
+                let fd =
+                    unsafe { libc::memfd_create(name.as_ptr() as *const _, libc::MFD_CLOEXEC) };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut file = unsafe { File::from_raw_fd(fd) };
+                io::copy(buffer, &mut file)?;
+                file.seek(SeekFrom::Start(0))?;
+                return Ok(Some(file));
+            }
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    /// Check if the input comes from a terminal, where a human may be typing.
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            InputStream::Inherited => unsafe { libc::isatty(libc::STDIN_FILENO) == 1 },
+            _ => false,
+        }
+    }
+
+    /// Wait at most `timeout` for data to be available. Return false if the timeout expired.
+    pub fn wait_readable(&self, timeout: Duration) -> bool {
+        let fd = match self.raw_fd() {
+            Some(fd) => fd,
+            None => return true,
+        };
+        let mut fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) > 0 }
+    }
+}
+
+impl Read for InputStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            // The standard input of the shell is not buffered, so the data following what a
+            // builtin reads is left for the next command sharing the same input
+            InputStream::Inherited => {
+                let n = unsafe {
+                    libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut _, buf.len())
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }
+            InputStream::Pipe(pipe) => pipe.read(buf),
+            InputStream::Buffer(buffer) => buffer.read(buf),
+            InputStream::File(file) => file.read(buf),
+        }
+    }
+}
+
+/// Standard streams of a builtin, so its output can be piped or redirected like the output
+/// of external commands.
+pub struct Streams<'a> {
+    pub stdin: &'a mut InputStream,
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+    /// Where `stdout` leads, for builtins running other commands
+    pub output: Output,
+}
+
+/// Destination of the standard output of a builtin.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Output {
+    /// Standard output of the shell
+    Inherited,
+    /// Kept aside for the next command of the pipeline
+    Piped,
+    /// File redirected with `>` or `>>`
+    File(RawFd),
+}
+
+/// Command run by the shell itself. It receives its arguments, without its name, and returns
+/// its status.
+pub trait Builtin {
+    fn run(&self, shell: &mut Osh, args: &[String], streams: &mut Streams) -> u32;
+}
+
+impl<F> Builtin for F
+where
+    F: Fn(&mut Osh, &[String], &mut Streams) -> u32,
+{
+    fn run(&self, shell: &mut Osh, args: &[String], streams: &mut Streams) -> u32 {
+        self(shell, args, streams)
+    }
+}
+
+/// Builtins available in the shell, by name.
+#[derive(Clone, Default)]
+pub struct Builtins {
+    builtins: HashMap<String, Rc<dyn Builtin>>,
+}
+
+impl Builtins {
+    /// Create the registry of the builtins shipped with the shell.
+    pub fn new() -> Builtins {
+        let mut builtins = Builtins::default();
+
+        builtins.register(
+            "abbr",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.abbr(args, io.stdout),
+        );
+        builtins.register(
+            "alias",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.alias(args, io.stdout),
+        );
+        builtins.register(
+            "builtin",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| {
+                command(shell, args, io, "builtin")
+            },
+        );
+        builtins.register(
+            "cd",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.cd(args, io.stdout),
+        );
+        builtins.register(
+            "command",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| {
+                command(shell, args, io, "command")
+            },
+        );
+        builtins.register("config", config);
+        builtins.register(
+            "dirs",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.dirs(args, io.stdout),
+        );
+        builtins.register(
+            "echo",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.echo(args, io.stdout),
+        );
+        builtins.register("eval", eval);
+        builtins.register("exec", exec);
+        builtins.register("export", export);
+        builtins.register(
+            "hash",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.hash(args, io.stdout),
+        );
+        builtins.register("history", history);
+        builtins.register(
+            "jobs",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.jobs(args, io.stdout),
+        );
+        builtins.register(
+            "kill",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.kill(args, io.stdout),
+        );
+        builtins.register(
+            "popd",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.popd(args, io.stdout),
+        );
+        builtins.register(
+            "printf",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.printf(args, io.stdout),
+        );
+        builtins.register(
+            "pushd",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.pushd(args, io.stdout),
+        );
+        builtins.register(
+            "pwd",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.pwd(args, io.stdout),
+        );
+        builtins.register(
+            "read",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.read(args, io),
+        );
+        builtins.register("reload", reload);
+        builtins.register(
+            "set",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.set(args, io.stdout),
+        );
+        builtins.register(
+            "shift",
+            |shell: &mut Osh, args: &[String], _: &mut Streams| shell.shift(args),
+        );
+        builtins.register("status", status);
+        builtins.register(
+            "times",
+            |shell: &mut Osh, _: &[String], io: &mut Streams| shell.times(io.stdout),
+        );
+        builtins.register(
+            "trap",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.trap(args, io.stdout),
+        );
+        builtins.register(
+            "trust",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.trust(args, io.stdout),
+        );
+        builtins.register(
+            "type",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| {
+                shell.describe(args, io.stdout, "type")
+            },
+        );
+        builtins.register(
+            "ulimit",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.ulimit(args, io.stdout),
+        );
+        builtins.register(
+            "umask",
+            |shell: &mut Osh, args: &[String], io: &mut Streams| shell.umask(args, io.stdout),
+        );
+        builtins.register(
+            "unalias",
+            |shell: &mut Osh, args: &[String], _: &mut Streams| shell.unalias(args),
+        );
+        builtins.register("unset", unset);
+        builtins.register(
+            "wait",
+            |shell: &mut Osh, args: &[String], _: &mut Streams| shell.wait(args),
+        );
+        builtins.register("z", |shell: &mut Osh, args: &[String], io: &mut Streams| {
+            shell.z(args, io.stdout)
+        });
+
+        builtins
+    }
+
+    /// Add a builtin, replacing any builtin with the same name.
+    pub fn register(&mut self, name: &str, builtin: impl Builtin + 'static) {
+        self.builtins.insert(name.to_string(), Rc::new(builtin));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Builtin>> {
+        self.builtins.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builtins.contains_key(name)
+    }
+
+    /// List the names of the builtins, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self
+            .builtins
+            .keys()
+            .map(|k| k.as_str())
+            .collect::<Vec<&str>>();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// `export NAME=VALUE` or `export NAME VALUE`: set an environment variable, or remove it when
+/// no value is supplied.
+fn export(shell: &mut Osh, args: &[String], _: &mut Streams) -> u32 {
+    let mut args = args.iter();
+    let env_var = match args.next() {
+        Some(v) => v.clone(),
+        None => {
+            werror!("No environment variable provided");
+            return 1;
+        }
+    };
+
+    // Both `export NAME VALUE` and `export NAME=VALUE` are supported
+    let (env_var, value) = match env_var.split_once('=') {
+        Some((name, value)) => (name.to_string(), Some(value)),
+        None => (env_var, args.next().map(|v| &v[..])),
+    };
+
+    match value {
+        Some(v) => shell.set_variable(&env_var, v),
+        None => shell.unset_variable(&env_var),
+    };
+
+    0
+}
+
+/// `unset NAME`: remove an environment variable.
+fn unset(shell: &mut Osh, args: &[String], _: &mut Streams) -> u32 {
+    match args.first() {
+        Some(v) => {
+            shell.unset_variable(v);
+            0
+        }
+        None => {
+            werror!("No environment variable provided");
+            1
+        }
+    }
+}
+
+/// `command` and `builtin` simply run their first argument, since its alias has been bypassed
+/// already. Only options such as `command -v` reach this point.
+fn command(shell: &mut Osh, args: &[String], io: &mut Streams, name: &str) -> u32 {
+    if args.is_empty() {
+        return 0;
+    }
+    shell.describe(args, io.stdout, name)
+}
+
+/// `config`: edit the configuration file with `$EDITOR` and reload it.
+/// `config check [FILE...]`: validate configuration files, by default the ones loaded, without
+/// applying them.
+fn config(shell: &mut Osh, args: &[String], io: &mut Streams) -> u32 {
+    if let Some((subcommand, files)) = args.split_first() {
+        if subcommand != "check" {
+            werror!("config: {}: unknown subcommand", subcommand);
+            return 2;
+        }
+        return config_check(shell, files, io);
+    }
+
+    let editor = match env::var("EDITOR") {
+        Ok(e) => e,
+        Err(_) => {
+            werror!("EDITOR variable not set. Cannot open configuration file");
+            return 1;
+        }
+    };
+
+    // Settings changed from the shell go to the user configuration file
+    let path = user_config_path();
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let child = Command::new(&editor)
+        .arg(path)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            let _ = child.wait();
+        }
+        Err(e) => {
+            werror!("config: {}: {}", editor, e);
+            return 1;
+        }
+    }
+
+    reload(shell, &[], io)
+}
+
+/// `config check`: report the errors of `files`, or of the configuration files loaded.
+fn config_check(shell: &Osh, files: &[String], io: &mut Streams) -> u32 {
+    let paths = if files.is_empty() {
+        config_layers(shell.config_path.as_deref())
+            .into_iter()
+            .filter(|(path, required)| *required || path.exists())
+            .map(|(path, _)| path)
+            .collect()
+    } else {
+        files.iter().map(PathBuf::from).collect::<Vec<PathBuf>>()
+    };
+
+    let mut status = 0;
+    for path in paths {
+        match check_config(&path) {
+            Ok(()) => {
+                if let Err(e) = writeln!(io.stdout, "{}: OK", path.display()) {
+                    werror!("config: write error: {}", e);
+                    return 1;
+                }
+            }
+            Err(e) => {
+                werror!("{}", e);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// `reload`: read the configuration files again. The current configuration is kept if one of
+/// them is invalid.
+fn reload(shell: &mut Osh, _: &[String], _: &mut Streams) -> u32 {
+    let (config, errors) = ConfigFile::load(shell.config_path.as_deref());
+    if !errors.is_empty() {
+        for error in errors {
+            werror!("{}", error);
+        }
+        werror!("Configuration not reloaded, the previous one is kept");
+        return 1;
+    }
+    shell.apply_config(config);
+    winfo!("Configuration file reloaded");
+    0
+}
+
+/// `status`: display the status of the last command.
+fn status(shell: &mut Osh, _: &[String], io: &mut Streams) -> u32 {
+    if let Err(e) = writeln!(io.stdout, "Status: {}", shell.status) {
+        werror!("status: write error: {}", e);
+        return 1;
+    }
+    0
+}
+
+/// `history`: display the lines entered in previous and current sessions.
+fn history(shell: &mut Osh, _: &[String], io: &mut Streams) -> u32 {
+    for (i, h) in shell.history().iter().enumerate() {
+        if let Err(e) = writeln!(io.stdout, "{:>3} :: {}", i, h) {
+            werror!("history: write error: {}", e);
+            return 1;
+        }
+    }
+    0
+}
+
+/// `eval ARG...`: run the arguments as a command line.
+fn eval(shell: &mut Osh, args: &[String], io: &mut Streams) -> u32 {
+    let line = args.join(" ");
+
+    // The commands run follow the redirections and the pipeline of `eval`
+    let input = match io.stdin.open_file() {
+        Ok(input) => input,
+        Err(e) => {
+            werror!("eval: cannot read the input: {}", e);
+            return 1;
+        }
+    };
+    let _stdin_guard = input
+        .as_ref()
+        .and_then(|f| StreamGuard::redirect(libc::STDIN_FILENO, f.as_raw_fd()));
+
+    match io.output {
+        Output::Inherited => shell.run_line(&line),
+        Output::File(fd) => {
+            let _stdout_guard = StreamGuard::redirect(libc::STDOUT_FILENO, fd);
+            shell.run_line(&line);
+        }
+        Output::Piped => {
+            if let Err(e) = capture_output(io.stdout, || shell.run_line(&line)) {
+                werror!("eval: cannot capture the output: {}", e);
+                return 1;
+            }
+        }
+    }
+    shell.status
+}
+
+/// `exec COMMAND [ARG...]`: replace the shell with `COMMAND`. Nothing is executed past this
+/// point unless the command cannot be run. Like in a subshell, `exec` does not replace the
+/// shell when its output is piped.
+fn exec(shell: &mut Osh, args: &[String], io: &mut Streams) -> u32 {
+    let (program, args) = match args.split_first() {
+        Some(command) => command,
+        None => return 0,
+    };
+
+    let mut command = Command::new(program);
+    command.args(args);
+    match io.stdin.open_file() {
+        Ok(Some(input)) => {
+            command.stdin(input);
+        }
+        Ok(None) => (),
+        Err(e) => {
+            werror!("exec: cannot read the input: {}", e);
+            return 1;
+        }
+    }
+    // The standard error is redirected already, see `StreamGuard`
+    match io.output {
+        Output::Inherited => (),
+        Output::File(fd) => {
+            let fd = unsafe { libc::dup(fd) };
+            if fd >= 0 {
+                command.stdout(unsafe { File::from_raw_fd(fd) });
+            }
+        }
+        Output::Piped => {
+            let mut child = match command.stdout(Stdio::piped()).spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    werror!("exec: {}: {}", program, e);
+                    return 126;
+                }
+            };
+            if let Some(mut output) = child.stdout.take() {
+                let _ = io::copy(&mut output, io.stdout);
+            }
+            return match child.wait() {
+                Ok(status) => Osh::exit_code(status),
+                Err(_) => 1,
+            };
+        }
+    }
+
+    shell.save_history();
+    let error = command.exec();
+    werror!("exec: {}: {}", program, error);
+    126
+}
+
+/// Run `f`, writing what the shell and the commands it runs print on their standard output
+/// to `output` instead. The output is collected through a pipe by another thread, so commands
+/// writing a lot do not block.
+fn capture_output(output: &mut dyn Write, f: impl FnOnce()) -> io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+    let writer = unsafe { File::from_raw_fd(fds[1]) };
+    let collector = thread::spawn(move || {
+        let mut data = Vec::new();
+        let _ = reader.read_to_end(&mut data);
+        data
+    });
+
+    // The pipe must only remain open as the standard output, so the reader sees its end
+    let guard = StreamGuard::redirect(libc::STDOUT_FILENO, writer.as_raw_fd());
+    drop(writer);
+    f();
+    drop(guard);
+
+    let data = collector.join().unwrap_or_default();
+    output.write_all(&data)
+}
+
+/// Point a standard stream of the shell to another file for as long as the guard is alive, so
+/// the errors reported by a builtin, or the output of the commands it runs, follow its
+/// redirections.
+pub struct StreamGuard {
+    fd: RawFd,
+    saved: RawFd,
+}
+
+impl StreamGuard {
+    pub fn redirect(fd: RawFd, target: RawFd) -> Option<StreamGuard> {
+        // Data written before belongs to the previous destination
+        if fd == libc::STDOUT_FILENO {
+            let _ = stdout().flush();
+        }
+        let saved = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if saved < 0 {
+            return None;
+        }
+        unsafe { libc::dup2(target, fd) };
+        Some(StreamGuard { fd, saved })
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if self.fd == libc::STDOUT_FILENO {
+            let _ = stdout().flush();
+        }
+        unsafe {
+            libc::dup2(self.saved, self.fd);
+            libc::close(self.saved);
+        }
+    }
+}
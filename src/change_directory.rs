@@ -0,0 +1,230 @@
+use crate::directory_config::DirectoryConfiguration;
+use crate::directory_stack::{is_stack_index, DirectoryStack};
+use crate::shell::Osh;
+use crate::utils::Utils;
+use crate::werror;
+
+use std::env::{self, set_var};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+use console::style;
+
+/// Check that `a` and `b` designate the same file.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Remove `.` and `..` components from `path` without resolving symlinks, so going up from a
+/// symlink leads back to where it was followed from.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Current directory as reached by the user, symlinks included: `PWD` if it still designates
+/// the current directory, the physical path otherwise.
+pub fn logical_current_dir() -> PathBuf {
+    let physical = env::current_dir().unwrap_or_default();
+    match env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() && normalize(&pwd) == pwd && same_file(&pwd, &physical) => {
+            pwd
+        }
+        _ => physical,
+    }
+}
+
+/// Search `dir` in the directories listed in `CDPATH`. The result must be displayed when it is
+/// not relative to the current directory.
+fn search_cdpath(dir: &str) -> Option<(PathBuf, bool)> {
+    let path = Path::new(dir);
+    if !matches!(path.components().next(), Some(Component::Normal(_))) {
+        return None;
+    }
+
+    let cdpath = env::var("CDPATH").ok()?;
+    cdpath.split(':').find_map(|entry| {
+        // An empty entry stands for the current directory
+        let candidate = if entry.is_empty() {
+            path.to_path_buf()
+        } else {
+            Path::new(entry).join(path)
+        };
+        if candidate.is_dir() {
+            Some((candidate, !entry.is_empty()))
+        } else {
+            None
+        }
+    })
+}
+
+pub trait ChangeDirectory {
+    fn set_directory(&mut self, target: &Path, physical: bool) -> bool;
+    fn cd(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn pwd(&self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl ChangeDirectory for Osh {
+    /// Change the current directory to `target` and update `PWD` and `OLDPWD`, so `cd -` can go
+    /// back. Errors are reported to the user.
+    /// Unless `physical` is set, `..` is handled lexically and `PWD` keeps symlinks.
+    fn set_directory(&mut self, target: &Path, physical: bool) -> bool {
+        // Save the location we're in before changing directory
+        let dir_before_cd = logical_current_dir();
+
+        let logical = normalize(&dir_before_cd.join(target));
+        let new_dir = if !physical && env::set_current_dir(&logical).is_ok() {
+            logical
+        } else {
+            // The logical path may not exist, e.g. with `..` after a symlink to a directory
+            // whose parent is not accessible
+            if let Err(e) = env::set_current_dir(target) {
+                werror!("Error: {}: '{}'", e, target.display());
+                return false;
+            }
+            env::current_dir().unwrap_or(logical)
+        };
+
+        // Update the last directory if need be
+        if new_dir != dir_before_cd {
+            set_var("OLDPWD", &dir_before_cd);
+        }
+        set_var("PWD", &new_dir);
+
+        // Apply the configuration files of the new directory
+        self.update_directory_config();
+        true
+    }
+
+    /// Implementation of the `cd` builtin:
+    ///
+    /// - `cd`: go to the home directory
+    /// - `cd DIR`: go to `DIR`, searched in `CDPATH` when it is a relative path
+    /// - `cd -`: go back to the previous directory
+    /// - `cd +N` or `cd -N`: rotate the directory stack, see `dirs -v` for indexes
+    /// - `-L`: keep symlinks in `PWD` and handle `..` lexically (default)
+    /// - `-P`: resolve symlinks
+    fn cd(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut physical = false;
+        let mut args = args;
+        while let Some((flag, rest)) = args.split_first() {
+            match flag.as_str() {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                "--" => {
+                    args = rest;
+                    break;
+                }
+                f if f.starts_with('-') && f.len() > 1 && !is_stack_index(f) => {
+                    werror!("cd: {}: invalid option", f);
+                    return 2;
+                }
+                _ => break,
+            }
+            args = rest;
+        }
+
+        // default to '~' of '/' as new directory if one was not provided
+        let new_dir = match args.first() {
+            Some(v) => v.clone(),
+            None => match env::var("HOME") {
+                Ok(val) => val,
+                Err(_) => {
+                    werror!("HOME variable not set. Using / as default target");
+                    "/".into()
+                }
+            },
+        };
+
+        // Use "+N" or "-N" to rotate the directory stack, like pushd does
+        if is_stack_index(&new_dir) {
+            return self.rotate_directory_stack(&new_dir, "cd");
+        }
+
+        // Use "-" to go to the last directory visited, and tell the user where it is
+        let (target, display) = if new_dir == "-" {
+            match env::var("OLDPWD") {
+                Ok(dir) => (PathBuf::from(dir), true),
+                Err(_) => {
+                    werror!("cd: OLDPWD not set");
+                    return 1;
+                }
+            }
+        } else {
+            // Perform variable expansion
+            let target = Osh::perform_expansion_on_single_element(&new_dir);
+            search_cdpath(&target).unwrap_or_else(|| (PathBuf::from(target), false))
+        };
+
+        // Save the location we're in before changing directory
+        let dir_before_cd = logical_current_dir();
+        if !self.set_directory(&target, physical) {
+            return 1;
+        }
+
+        let cwd = logical_current_dir();
+        if display {
+            if let Err(e) = writeln!(output, "{}", cwd.display()) {
+                werror!("cd: write error: {}", e);
+            }
+        }
+
+        // Keep every directory visited on the stack if requested
+        if self.config.auto_pushd && cwd != dir_before_cd {
+            self.directory_stack.insert(0, dir_before_cd);
+        }
+        // Rank the directory so `z` can jump back to it
+        self.frecency.add(&cwd);
+
+        0
+    }
+
+    /// Implementation of the `pwd` builtin:
+    ///
+    /// - `-L`: display the current directory as reached, symlinks included (default)
+    /// - `-P`: display the current directory with symlinks resolved
+    fn pwd(&self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut physical = false;
+        for arg in args {
+            match arg.as_str() {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                _ => {
+                    werror!("pwd: {}: invalid option", arg);
+                    return 2;
+                }
+            }
+        }
+
+        let cwd = if physical {
+            match env::current_dir() {
+                Ok(d) => d,
+                Err(e) => {
+                    werror!("pwd: {}", e);
+                    return 1;
+                }
+            }
+        } else {
+            logical_current_dir()
+        };
+
+        if let Err(e) = writeln!(output, "{}", cwd.display()) {
+            werror!("pwd: write error: {}", e);
+            return 1;
+        }
+        0
+    }
+}
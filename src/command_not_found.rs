@@ -0,0 +1,172 @@
+use crate::alias::Alias;
+use crate::shell::Osh;
+use crate::werror;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use console::style;
+
+/// Name of the alias run in place of commands that cannot be found, with the command and its
+/// arguments appended, like the function of the same name in bash
+const HANDLER: &str = "command_not_found_handle";
+
+/// Maximum number of suggestions displayed
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Compute the edit distance between `a` and `b`, where swapping two adjacent characters
+/// counts as a single edit so `gti` is close to `git`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<char>>();
+    let b = b.chars().collect::<Vec<char>>();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in distances[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            distances[i][j] = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distances[i][j] = distances[i][j].min(distances[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+/// Explain why `command` exists but cannot be run, if that is the case.
+fn not_executable_reason(command: &str) -> Option<&'static str> {
+    let candidates = if command.contains('/') {
+        vec![Path::new(command).to_path_buf()]
+    } else {
+        let path = env::var("PATH").unwrap_or_default();
+        env::split_paths(&path)
+            .map(|dir| dir.join(command))
+            .collect()
+    };
+
+    candidates.iter().find_map(|candidate| {
+        let metadata = candidate.metadata().ok()?;
+        if metadata.is_dir() {
+            // Directories in PATH named like the command are not worth reporting
+            if command.contains('/') {
+                Some("Is a directory")
+            } else {
+                None
+            }
+        } else {
+            Some("Permission denied")
+        }
+    })
+}
+
+pub trait CommandNotFound {
+    fn command_not_found(&mut self, command: &str, args: &[String]) -> u32;
+    fn suggest_commands(&self, command: &str) -> Vec<String>;
+}
+
+impl CommandNotFound for Osh {
+    /// Report that `command` cannot be run and return the status to use: 126 if it exists but
+    /// cannot be executed, 127 otherwise.
+    /// If the `command_not_found_handle` alias exists, it is run instead.
+    fn command_not_found(&mut self, command: &str, args: &[String]) -> u32 {
+        if !self.in_not_found_handler && self.lookup_aliases(HANDLER).is_some() {
+            let line = [HANDLER, command]
+                .iter()
+                .map(|w| w.to_string())
+                .chain(args.iter().cloned())
+                .map(|w| shell_words::quote(&w).to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            // The handler itself may run unknown commands, which must not call it again
+            self.in_not_found_handler = true;
+            self.run_line(&line);
+            self.in_not_found_handler = false;
+            return self.status;
+        }
+
+        if let Some(reason) = not_executable_reason(command) {
+            werror!("{}: {}", command, reason);
+            return 126;
+        }
+
+        let suggestions = self
+            .suggest_commands(command)
+            .iter()
+            .map(|s| format!("`{}`", s))
+            .collect::<Vec<String>>();
+        match suggestions.len() {
+            0 => {
+                werror!("{}: command not found", command);
+            }
+            1 => {
+                werror!(
+                    "{}: command not found, did you mean {}?",
+                    command,
+                    suggestions[0]
+                );
+            }
+            _ => {
+                werror!(
+                    "{}: command not found, did you mean one of {}?",
+                    command,
+                    suggestions.join(", ")
+                );
+            }
+        }
+
+        127
+    }
+
+    /// List the aliases, builtins and executables of PATH whose names are close to
+    /// `command`, closest first.
+    fn suggest_commands(&self, command: &str) -> Vec<String> {
+        // Paths are not worth correcting
+        if command.contains('/') {
+            return Vec::new();
+        }
+
+        let mut names = HashSet::new();
+        names.extend(self.aliases.keys().cloned());
+        names.extend(self.builtins.names().iter().map(|b| b.to_string()));
+
+        let path = env::var("PATH").unwrap_or_default();
+        for dir in env::split_paths(&path) {
+            if let Ok(entries) = fs::read_dir(dir) {
+                for entry in entries.flatten() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        names.insert(name.to_string());
+                    }
+                }
+            }
+        }
+        names.remove(HANDLER);
+
+        // Allow more typos in longer names
+        let max_distance = (command.chars().count() / 3).clamp(1, 3);
+        let mut suggestions = names
+            .into_iter()
+            .map(|name| (edit_distance(command, &name), name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect::<Vec<(usize, String)>>();
+        suggestions.sort();
+
+        suggestions
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
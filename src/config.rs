@@ -1,18 +1,70 @@
 // use crate::utils::{
 //     Utils,
 // };
+use crate::alias::AliasKind;
+use crate::command_not_found::edit_distance;
 use crate::shell::Osh;
 use crate::utils::Utils;
-use crate::{wdebug, werror};
+use crate::{wdebug, werror, wwarning};
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use serde_yaml::{Mapping, Value};
 use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use console::style;
 
-#[derive(Debug, Deserialize)]
+/// Options of the configuration file, to report the unknown ones
+const KEYS: &[&str] = &[
+    "version",
+    "aliases",
+    "abbreviations",
+    "prompt",
+    "debug",
+    "username",
+    "hostname",
+    "auto_pushd",
+    "autocd",
+    "report_time_threshold",
+    "report_time_notification",
+];
+
+/// Version of the layout of configuration files. Files without `version` are YAML files of
+/// version 1, which had `aliases`, `global_aliases` and `suffix_aliases` at the top level.
+pub const CONFIG_VERSION: u64 = 2;
+
+fn current_version() -> u64 {
+    CONFIG_VERSION
+}
+
+/// Aliases of every kind, gathered under `aliases`
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AliasTable {
+    #[serde(default)]
+    pub command: HashMap<String, String>,
+    /// Aliases expanded anywhere in the command line
+    #[serde(default)]
+    pub global: HashMap<String, String>,
+    /// Programs used to run files with a given extension, without the dot
+    #[serde(default)]
+    pub suffix: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct ConfigFile {
-    pub aliases: HashMap<String, String>,
+    /// Layout of the file, see `CONFIG_VERSION`
+    #[serde(default = "current_version")]
+    pub version: u64,
+    #[serde(default)]
+    pub aliases: AliasTable,
+    /// Words replaced with their expansion while typing a command
+    #[serde(default)]
+    pub abbreviations: HashMap<String, String>,
     #[serde(default = "Osh::default_prompt")]
     pub prompt: String,
     #[serde(default)]
@@ -21,34 +73,793 @@ pub struct ConfigFile {
     pub username: String,
     #[serde(default = "Osh::get_hostname")]
     pub hostname: String,
+    /// Push the directory left on the directory stack every time `cd` is used
+    #[serde(default)]
+    pub auto_pushd: bool,
+    /// Go to directories used as command names, as if they were the argument of `cd`
+    #[serde(default)]
+    pub autocd: bool,
+    /// Display how long a command took when it runs for longer than this duration, such as
+    /// `5s`, `500ms` or `2m`
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub report_time_threshold: Option<Duration>,
+    /// How to notify that a command exceeding `report_time_threshold` finished, when the
+    /// terminal does not have the focus
+    #[serde(default)]
+    pub report_time_notification: Notification,
+}
+
+/// Notification sent to the terminal when a long command finishes while it does not have the
+/// focus. Terminals which do not report their focus are always notified.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Notification {
+    #[default]
+    None,
+    /// Ring the bell, which usually sets the urgency hint of the window
+    Bell,
+    /// Desktop notification through the OSC 777 escape sequence
+    Osc777,
+}
+
+/// Parse a duration such as `5s`, `500ms`, `2m` or `1h`. Numbers without unit are seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" | "min" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    // Durations too long to be represented are invalid too
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawDuration {
+        Seconds(f64),
+        Text(String),
+    }
+
+    match Option::<RawDuration>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(RawDuration::Seconds(seconds)) => match Duration::try_from_secs_f64(seconds) {
+            Ok(duration) => Ok(Some(duration)),
+            Err(_) => Err(serde::de::Error::custom(format!(
+                "invalid duration: {}",
+                seconds
+            ))),
+        },
+        Some(RawDuration::Text(text)) => match parse_duration(&text) {
+            Some(duration) => Ok(Some(duration)),
+            None => Err(serde::de::Error::custom(format!(
+                "invalid duration: {}",
+                text
+            ))),
+        },
+    }
+}
+
+/// Directory of the configuration shared by all the users of the system
+const SYSTEM_CONFIG_DIR: &str = "/etc/osh";
+
+/// Location of the user configuration file before `$XDG_CONFIG_HOME/osh/config.yaml`
+const LEGACY_CONFIG_PATH: &str = "~/.shell.yaml";
+
+/// Environment variable designating an additional configuration file
+const CONFIG_ENV: &str = "OSH_CONFIG";
+
+/// Languages configuration files are written in, according to their extension
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn of(path: &Path) -> Format {
+        if path.extension() == Some(OsStr::new("toml")) {
+            Format::Toml
+        } else {
+            Format::Yaml
+        }
+    }
+
+    /// Parse `content` as a document of any shape.
+    fn parse(self, content: &str) -> Result<Value, String> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Parse `content` as a configuration, so errors include their position.
+    fn parse_config(self, content: &str) -> Result<ConfigFile, String> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Configuration file of `dir`: `config.yaml`, or `config.toml` if only the latter exists.
+fn config_file_in(dir: &Path) -> PathBuf {
+    let yaml = dir.join("config.yaml");
+    let toml = dir.join("config.toml");
+    if !yaml.exists() && toml.exists() {
+        toml
+    } else {
+        yaml
+    }
+}
+
+/// Configuration file of the user: `$XDG_CONFIG_HOME/osh/config.yaml` or `config.toml`, or
+/// `~/.shell.yaml` if only the latter exists.
+pub fn user_config_path() -> PathBuf {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(Osh::perform_expansion_on_single_element("~/.config")),
+    };
+    let path = config_file_in(&config_home.join("osh"));
+    let legacy = PathBuf::from(Osh::perform_expansion_on_single_element(LEGACY_CONFIG_PATH));
+    if !path.exists() && legacy.exists() {
+        legacy
+    } else {
+        path
+    }
+}
+
+/// Configuration files, from the lowest to the highest priority, and whether they must exist:
+/// the system one, the user one, the one designated by `OSH_CONFIG` and the one given with
+/// `--config`.
+pub fn config_layers(config_path: Option<&Path>) -> Vec<(PathBuf, bool)> {
+    let mut layers = vec![
+        (config_file_in(Path::new(SYSTEM_CONFIG_DIR)), false),
+        (user_config_path(), false),
+    ];
+    match env::var(CONFIG_ENV) {
+        Ok(path) if !path.is_empty() => layers.push((PathBuf::from(path), true)),
+        _ => (),
+    }
+    if let Some(path) = config_path {
+        layers.push((path.to_path_buf(), true));
+    }
+    layers
+}
+
+/// Whether `content` holds nothing but comments and blank lines, which the YAML parser rejects.
+fn is_blank(content: &str) -> bool {
+    content
+        .lines()
+        .all(|l| l.trim().is_empty() || l.trim_start().starts_with('#'))
+}
+
+/// Line of `content` defining the top-level option `key`, in YAML or TOML.
+fn key_line(content: &str, key: &str) -> Option<usize> {
+    content.lines().position(|line| {
+        if line.starts_with([' ', '\t']) {
+            return false;
+        }
+        let toml_key = line
+            .trim_start_matches('[')
+            .split(['=', ']', '.'])
+            .next()
+            .map(str::trim);
+        entry_key(line).as_deref() == Some(key) || toml_key == Some(key)
+    })
+}
+
+/// Report the first option of `document`, the configuration file at `path`, which is not part
+/// of `KEYS`, suggesting the closest known option.
+fn check_keys(path: &Path, content: &str, document: &Mapping) -> Result<(), String> {
+    let key = match document
+        .iter()
+        .filter_map(|(key, _)| key.as_str())
+        .find(|key| !KEYS.contains(key))
+    {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+
+    let mut message = format!("{}: unknown option `{}`", path.display(), key);
+    if let Some(number) = key_line(content, key) {
+        message += &format!(" at line {} column 1", number + 1);
+    }
+    let closest = KEYS
+        .iter()
+        .map(|k| (edit_distance(key, k), k))
+        .filter(|(distance, _)| *distance <= 3)
+        .min();
+    if let Some((_, suggestion)) = closest {
+        message += &format!(", did you mean `{}`?", suggestion);
+    }
+    Err(message)
+}
+
+/// Files whose migration was reported already, so it is reported once per session
+static MIGRATION_REPORTED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Whether `document`, a configuration file without `version`, has the layout of version 1:
+/// aliases defined right under `aliases`, or `global_aliases` and `suffix_aliases` sections.
+fn is_version_1(document: &Mapping) -> bool {
+    let flat_aliases = match document.get(&Value::from("aliases")) {
+        Some(Value::Mapping(aliases)) => aliases.iter().any(|(_, v)| !v.is_mapping()),
+        _ => false,
+    };
+    flat_aliases
+        || AliasKind::ALL
+            .iter()
+            .filter(|kind| kind.section() != "aliases")
+            .any(|kind| document.contains_key(&Value::from(kind.section())))
+}
+
+/// Bring `document`, the configuration file at `path`, to the layout of `CONFIG_VERSION`, and
+/// warn the user that the file should be updated if its layout changed. Return whether it
+/// was migrated.
+fn migrate(path: &Path, format: Format, document: &mut Mapping) -> Result<bool, String> {
+    let version = match document.get(&Value::from("version")) {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("{}: version: expected a number", path.display()))?,
+        // TOML files are more recent than versions
+        None if format == Format::Toml => CONFIG_VERSION,
+        None if is_version_1(document) => 1,
+        None => CONFIG_VERSION,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "{}: version {} is not supported, the latest version is {}",
+            path.display(),
+            version,
+            CONFIG_VERSION
+        ));
+    }
+
+    let mut migrated = false;
+    if version < 2 {
+        // Aliases of every kind are gathered in a single table since version 2. Files without
+        // aliases have the same layout in both versions.
+        let mut table = Mapping::new();
+        for kind in AliasKind::ALL.iter().copied() {
+            if let Some(aliases) = document.remove(&Value::from(kind.section())) {
+                table.insert(Value::from(kind.key()), aliases);
+            }
+        }
+        if !table.is_empty() {
+            document.insert(Value::from("aliases"), Value::Mapping(table));
+            migrated = true;
+        }
+    }
+    let mut reported = MIGRATION_REPORTED.lock().unwrap_or_else(|e| e.into_inner());
+    if migrated && !reported.iter().any(|p| p == path) {
+        reported.push(path.to_path_buf());
+        wwarning!(
+            "{}: configuration of version 1 migrated. Set `version: {}` and move `aliases`, \
+            `global_aliases` and `suffix_aliases` to `command`, `global` and `suffix` under \
+            `aliases`",
+            path.display(),
+            CONFIG_VERSION
+        );
+    }
+    document.insert(Value::from("version"), Value::from(CONFIG_VERSION));
+    Ok(migrated)
+}
+
+/// Read, migrate and validate the configuration file at `path`, if it exists. Errors include
+/// the position of the faulty option.
+fn read_layer(path: &Path, required: bool) -> Result<Option<Value>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(e) => {
+            return Err(format!(
+                "Cannot open configuration file '{}': {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    if is_blank(&content) {
+        return Ok(None);
+    }
+    let format = Format::of(path);
+    let mut document = match format.parse(&content) {
+        // An empty file does not override anything
+        Ok(Value::Null) => return Ok(None),
+        Ok(Value::Mapping(document)) => document,
+        Ok(_) => return Err(format!("{}: expected a mapping of options", path.display())),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let migrated = migrate(path, format, &mut document)?;
+    check_keys(path, &content, &document)?;
+
+    // Parse the file on its own as well, since the values of the merged layers cannot be
+    // located. Positions are lost once migrated.
+    let result = if migrated {
+        serde_yaml::from_value::<ConfigFile>(Value::Mapping(document.clone()))
+            .map_err(|e| e.to_string())
+    } else {
+        format.parse_config(&content)
+    };
+    result.map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Some(Value::Mapping(document)))
+}
+
+/// Validate the configuration file at `path` without applying it.
+pub fn check_config(path: &Path) -> Result<(), String> {
+    read_layer(path, true).map(|_| ())
+}
+
+/// Merge the configuration `layer` into `base`: mappings, such as `aliases`, are merged key by
+/// key, other values are replaced.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Mapping(base), Value::Mapping(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(current) => merge(current, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Format `value` as a YAML scalar, quoted if need be.
+fn yaml_scalar(value: &str) -> String {
+    serde_yaml::to_string(value)
+        .map(|s| s.trim_start_matches("---").trim().to_string())
+        .unwrap_or_else(|_| format!("{:?}", value))
+}
+
+/// Key of the mapping entry on `line`, unquoted, if any.
+fn entry_key(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let key = match line.chars().next() {
+        Some(quote @ ('\'' | '"')) => {
+            let end = line[1..].find(quote)? + 1;
+            &line[..=end]
+        }
+        _ => &line[..line.find(':')?],
+    };
+    serde_yaml::from_str::<String>(key).ok()
+}
+
+/// Position of the end of the block mapping starting at `start`: the first line which is not
+/// indented.
+fn block_end(lines: &[String], start: usize) -> usize {
+    lines[start + 1..]
+        .iter()
+        .position(|l| !l.is_empty() && !l.starts_with([' ', '\t', '#']))
+        .map_or(lines.len(), |i| start + 1 + i)
+}
+
+/// Define the alias `name` as `value`, or remove it if `value` is not set, in the mapping
+/// `section` of the YAML document `content`. Only the lines of the alias are touched, so comments and the order of the
+/// other entries are kept.
+fn update_alias(
+    content: &str,
+    section: &str,
+    name: &str,
+    value: Option<&str>,
+) -> Result<String, String> {
+    let header = format!("{}:", section);
+    let mut lines = content.lines().map(String::from).collect::<Vec<String>>();
+    let entry = |indent: &str| {
+        value.map(|v| format!("{}{}: {}", indent, yaml_scalar(name), yaml_scalar(v)))
+    };
+
+    let start = lines.iter().position(|l| l.starts_with(&header));
+    match start {
+        None => {
+            if let Some(entry) = entry("  ") {
+                lines.push(header.clone());
+                lines.push(entry);
+            }
+        }
+        Some(start) => {
+            // Flow mappings such as `aliases: {}` are turned into block mappings
+            let inline = lines[start][header.len()..].trim().to_string();
+            if !inline.is_empty() && !inline.starts_with('#') {
+                let aliases: HashMap<String, String> = serde_yaml::from_str(&inline)
+                    .map_err(|e| format!("cannot parse the aliases: {}", e))?;
+                let mut names = aliases.keys().collect::<Vec<&String>>();
+                names.sort();
+                let entries = names
+                    .into_iter()
+                    .map(|n| format!("  {}: {}", yaml_scalar(n), yaml_scalar(&aliases[n])));
+                lines.splice(
+                    start..=start,
+                    std::iter::once(header.clone()).chain(entries),
+                );
+            }
+
+            let end = block_end(&lines, start);
+            let indent = lines[start + 1..end]
+                .iter()
+                .find(|l| entry_key(l).is_some())
+                .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+                .unwrap_or_else(|| "  ".into());
+
+            let existing = (start + 1..end).find(|i| {
+                lines[*i].starts_with(&indent)
+                    && !lines[*i][indent.len()..].starts_with([' ', '\t'])
+                    && entry_key(&lines[*i]).as_deref() == Some(name)
+            });
+            match (existing, entry(&indent)) {
+                (Some(i), entry) => {
+                    // Values may be continued on more indented lines
+                    let last = (i + 1..end)
+                        .find(|j| {
+                            let l = &lines[*j];
+                            l.trim().is_empty() || l.len() - l.trim_start().len() <= indent.len()
+                        })
+                        .unwrap_or(end);
+                    lines.splice(i..last, entry);
+                }
+                (None, Some(entry)) => {
+                    // Insert after the last entry, before trailing blank lines and comments
+                    let mut position = end;
+                    while position > start + 1 && entry_key(&lines[position - 1]).is_none() {
+                        position -= 1;
+                    }
+                    lines.insert(position, entry);
+                }
+                (None, None) => (),
+            }
+
+            // An empty block mapping would be null
+            let end = block_end(&lines, start);
+            if !lines[start + 1..end].iter().any(|l| entry_key(l).is_some()) {
+                lines[start] = format!("{} {{}}", header);
+            }
+        }
+    }
+
+    let mut result = lines.join("\n");
+    result.push('\n');
+
+    // Make sure the file still means what it should before overwriting it
+    let document: serde_yaml::Value =
+        serde_yaml::from_str(&result).map_err(|e| format!("cannot update the aliases: {}", e))?;
+    let saved = document
+        .get(section)
+        .and_then(|a| a.get(name))
+        .and_then(|v| v.as_str());
+    if saved != value {
+        return Err("cannot update the aliases: unexpected layout".into());
+    }
+    Ok(result)
+}
+
+/// Define the alias `name` as `value`, or remove it if `value` is not set, in the table
+/// `aliases.KEY` of the YAML document `content`. The `aliases` block is updated as a document of
+/// its own by `update_alias`.
+fn update_alias_table(
+    content: &str,
+    key: &str,
+    name: &str,
+    value: Option<&str>,
+) -> Result<String, String> {
+    let mut lines = content.lines().map(String::from).collect::<Vec<String>>();
+    let start = match lines
+        .iter()
+        .position(|l| !l.starts_with([' ', '\t']) && entry_key(l).as_deref() == Some("aliases"))
+    {
+        Some(start) => start,
+        None => {
+            lines.push("aliases:".into());
+            lines.len() - 1
+        }
+    };
+
+    // Only empty flow mappings are expected after the key
+    let inline = lines[start][lines[start].find(':').unwrap_or(0) + 1..].trim();
+    if inline == "{}" {
+        lines[start] = "aliases:".into();
+    } else if !inline.is_empty() && !inline.starts_with('#') {
+        return Err("cannot update the aliases: unexpected layout".into());
+    }
+
+    // Comments and blank lines before the next option belong to it
+    let mut end = block_end(&lines, start);
+    while end > start + 1 && (lines[end - 1].trim().is_empty() || lines[end - 1].starts_with('#')) {
+        end -= 1;
+    }
+    let indent = lines[start + 1..end]
+        .iter()
+        .find(|l| entry_key(l).is_some())
+        .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+        .unwrap_or_else(|| "  ".into());
+
+    let table = lines[start + 1..end]
+        .iter()
+        .map(|l| l.strip_prefix(&indent).unwrap_or_else(|| l.trim_start()))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let table = update_alias(&table, key, name, value)?;
+    let table = table
+        .lines()
+        .map(|l| {
+            if l.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, l)
+            }
+        })
+        .collect::<Vec<String>>();
+    lines.splice(start + 1..end, table);
+
+    let mut result = lines.join("\n");
+    result.push('\n');
+
+    // Make sure the file still means what it should before overwriting it
+    let document: serde_yaml::Value =
+        serde_yaml::from_str(&result).map_err(|e| format!("cannot update the aliases: {}", e))?;
+    let saved = document
+        .get("aliases")
+        .and_then(|a| a.get(key))
+        .and_then(|a| a.get(name))
+        .and_then(|v| v.as_str());
+    if saved != value {
+        return Err("cannot update the aliases: unexpected layout".into());
+    }
+    Ok(result)
+}
+
+/// Define the alias `name` of the given `kind` as `value`, or remove it if `value` is not set,
+/// in the configuration file of the user. Files of version 1 keep their layout.
+pub fn save_alias(kind: AliasKind, name: &str, value: Option<&str>) -> Result<(), String> {
+    let path = user_config_path();
+    if Format::of(&path) == Format::Toml {
+        return Err(format!(
+            "{}: TOML configuration files cannot be updated, use `config` to edit it",
+            path.display()
+        ));
+    }
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+
+    let document = if is_blank(&content) {
+        Value::Null
+    } else {
+        serde_yaml::from_str::<Value>(&content).map_err(|e| format!("{}: {}", path.display(), e))?
+    };
+    let content = match document.get("version") {
+        Some(_) => update_alias_table(&content, kind.key(), name, value)?,
+        None if document.as_mapping().is_some_and(is_version_1) => {
+            update_alias(&content, kind.section(), name, value)?
+        }
+        None => {
+            let content = format!("version: {}\n{}", CONFIG_VERSION, content);
+            update_alias_table(&content, kind.key(), name, value)?
+        }
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    std::fs::write(&path, content).map_err(|e| format!("{}: {}", path.display(), e))
 }
 
 impl ConfigFile {
-    pub fn new() -> ConfigFile {
-        let mut config_file: Option<ConfigFile> = None;
-
-        // Try to read the configuration file
-        match std::fs::File::open(Osh::perform_expansion_on_single_element("~/.shell.yaml")) {
-            Ok(f) => {
-                // Load aliases
-                config_file = serde_yaml::from_reader(f).unwrap();
-            }
-            Err(_) => {
-                werror!("Cannot open configuration file '~/.shell.yaml'");
-            }
-        };
-        match config_file {
-            Some(c) => {
-                wdebug!(c, "Config file: {:#?}", c);
-                c
-            }
-            None => ConfigFile {
-                aliases: HashMap::new(),
-                prompt: Osh::default_prompt(),
-                debug: false,
-                username: Osh::get_username(),
-                hostname: Osh::get_hostname(),
-            },
+    /// Load the configuration files returned by `config_layers`, the settings of each file
+    /// overriding the ones of the previous files. `config_path` is the file given with
+    /// `--config`, if any. Invalid files are skipped and reported along with the configuration.
+    pub fn load(config_path: Option<&Path>) -> (ConfigFile, Vec<String>) {
+        let mut merged = Value::Mapping(Mapping::new());
+        let mut errors = Vec::new();
+        for (path, required) in config_layers(config_path) {
+            match read_layer(&path, required) {
+                Ok(Some(layer)) => merge(&mut merged, layer),
+                Ok(None) => (),
+                Err(e) => errors.push(e),
+            }
         }
+
+        let config = serde_yaml::from_value(merged).unwrap_or_else(|e| {
+            errors.push(format!("Invalid configuration: {}", e));
+            ConfigFile::default()
+        });
+        wdebug!(config, "Config file: {:#?}", config);
+        (config, errors)
+    }
+
+    /// Load the configuration like `load` does, reporting invalid files.
+    pub fn new(config_path: Option<&Path>) -> ConfigFile {
+        let (config, errors) = ConfigFile::load(config_path);
+        for error in errors {
+            werror!("{}", error);
+        }
+        config
+    }
+}
+
+impl Default for ConfigFile {
+    fn default() -> ConfigFile {
+        ConfigFile {
+            version: CONFIG_VERSION,
+            aliases: AliasTable::default(),
+            abbreviations: HashMap::new(),
+            prompt: Osh::default_prompt(),
+            debug: false,
+            username: Osh::get_username(),
+            hostname: Osh::get_hostname(),
+            auto_pushd: false,
+            autocd: false,
+            report_time_threshold: None,
+            report_time_notification: Notification::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_alias_keeps_layout() {
+        let cases: &[(&str, &str, Option<&str>, &str)] = &[
+            // New section
+            (
+                "prompt: \"> \"\n",
+                "ll",
+                Some("ls -l"),
+                "prompt: \"> \"\naliases:\n  ll: ls -l\n",
+            ),
+            // New entry after the last one, before trailing comments
+            (
+                "aliases:\n  # listing\n  ll: ls -l\n# end\n",
+                "la",
+                Some("ls -a"),
+                "aliases:\n  # listing\n  ll: ls -l\n  la: ls -a\n# end\n",
+            ),
+            // Replaced entry, with the indentation of the file
+            (
+                "aliases:\n    ll: ls -l # long\n    la: ls -a\n",
+                "ll",
+                Some("ls -lh"),
+                "aliases:\n    ll: ls -lh\n    la: ls -a\n",
+            ),
+            // Multi-line value
+            (
+                "aliases:\n  ll: >\n    ls\n    -l\n  la: ls -a\n",
+                "ll",
+                Some("ls -l"),
+                "aliases:\n  ll: ls -l\n  la: ls -a\n",
+            ),
+            // Flow mapping
+            (
+                "aliases: {ll: ls -l, g: git}\n",
+                "la",
+                Some("ls -a"),
+                "aliases:\n  g: git\n  ll: ls -l\n  la: ls -a\n",
+            ),
+            // Removed entry
+            (
+                "aliases:\n  ll: ls -l\n  la: ls -a\n",
+                "ll",
+                None,
+                "aliases:\n  la: ls -a\n",
+            ),
+            // Removed last entry
+            (
+                "aliases:\n  ll: ls -l\ndebug: true\n",
+                "ll",
+                None,
+                "aliases: {}\ndebug: true\n",
+            ),
+            // Quoted values
+            (
+                "aliases: {}\n",
+                "g",
+                Some("git: \"x\""),
+                "aliases:\n  g: \"git: \\\"x\\\"\"\n",
+            ),
+        ];
+
+        for (content, name, value, expected) in cases {
+            assert_eq!(
+                update_alias(content, "aliases", name, *value).as_deref(),
+                Ok(*expected),
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn update_alias_table_keeps_layout() {
+        let cases: &[(&str, &str, &str, Option<&str>, &str)] = &[
+            // New table
+            (
+                "version: 2\n",
+                "command",
+                "ll",
+                Some("ls -l"),
+                "version: 2\naliases:\n  command:\n    ll: ls -l\n",
+            ),
+            // Empty flow mapping
+            (
+                "version: 2\naliases: {}\n",
+                "global",
+                "G",
+                Some("| grep"),
+                "version: 2\naliases:\n  global:\n    G: \"| grep\"\n",
+            ),
+            // Other tables and the comments of the next option are kept
+            (
+                "version: 2\naliases:\n  # commands\n  command:\n    ll: ls -l\n\n# prompt\nprompt: \"> \"\n",
+                "suffix",
+                "pdf",
+                Some("zathura"),
+                "version: 2\naliases:\n  # commands\n  command:\n    ll: ls -l\n  suffix:\n    pdf: zathura\n\n# prompt\nprompt: \"> \"\n",
+            ),
+            // Removed entry
+            (
+                "version: 2\naliases:\n  command:\n    ll: ls -l\n  global: {G: \"| grep\"}\n",
+                "command",
+                "ll",
+                None,
+                "version: 2\naliases:\n  command: {}\n  global: {G: \"| grep\"}\n",
+            ),
+        ];
+
+        for (content, key, name, value, expected) in cases {
+            assert_eq!(
+                update_alias_table(content, key, name, *value).as_deref(),
+                Ok(*expected),
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn update_alias_table_rejects_unexpected_layouts() {
+        let content = "version: 2\naliases: {command: {ll: ls -l}}\n";
+        assert!(update_alias_table(content, "command", "la", Some("ls -a")).is_err());
+    }
+
+    #[test]
+    fn version_1_aliases_are_migrated() {
+        let path = Path::new("config.yaml");
+        let mut document: Mapping =
+            serde_yaml::from_str("aliases: {ll: ls -l}\nsuffix_aliases: {pdf: zathura}\n").unwrap();
+        assert_eq!(migrate(path, Format::Yaml, &mut document), Ok(true));
+        let config: ConfigFile = serde_yaml::from_value(Value::Mapping(document)).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.aliases.command["ll"], "ls -l");
+        assert_eq!(config.aliases.suffix["pdf"], "zathura");
+
+        // Files without aliases have the same layout in both versions
+        let mut document: Mapping = serde_yaml::from_str("debug: true\n").unwrap();
+        assert_eq!(migrate(path, Format::Yaml, &mut document), Ok(false));
+
+        // Files of version 2 may lack `version`
+        let mut document: Mapping =
+            serde_yaml::from_str("aliases: {command: {ll: ls -l}}\n").unwrap();
+        assert_eq!(migrate(path, Format::Yaml, &mut document), Ok(false));
+        let config: ConfigFile = serde_yaml::from_value(Value::Mapping(document)).unwrap();
+        assert_eq!(config.aliases.command["ll"], "ls -l");
     }
 }
@@ -0,0 +1,197 @@
+use crate::alias::AliasKind;
+use crate::config::{config_layers, ConfigFile};
+use crate::shell::Osh;
+use crate::{werror, winfo};
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+use console::style;
+
+/// Events meaning a file of a watched directory was written, replaced or removed. Editors
+/// often save files by renaming a new file over them.
+const EVENTS: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+/// Watch configuration files with inotify. Their directories are watched rather than the files
+/// themselves, so files replaced by editors and files created later are noticed too.
+pub struct ConfigWatcher {
+    fd: RawFd,
+    /// Names of the configuration files, by watch descriptor of their directory
+    files: HashMap<i32, Vec<OsString>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(paths: &[PathBuf]) -> Option<ConfigWatcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+
+        let mut files: HashMap<i32, Vec<OsString>> = HashMap::new();
+        for path in paths {
+            let (dir, name) = match (path.parent(), path.file_name()) {
+                (Some(dir), Some(name)) => (dir, name),
+                _ => continue,
+            };
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let dir = match CString::new(dir.as_os_str().as_bytes()) {
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+            // Directories which do not exist, such as /etc/osh, are not watched
+            let wd = unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), EVENTS) };
+            if wd >= 0 {
+                files.entry(wd).or_default().push(name.to_os_string());
+            }
+        }
+
+        Some(ConfigWatcher { fd, files })
+    }
+
+    /// Whether one of the configuration files changed since the last call.
+    pub fn changed(&mut self) -> bool {
+        let header = size_of::<libc::inotify_event>();
+        let mut buffer = [0u8; 4096];
+        let mut changed = false;
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if read <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset + header <= read as usize {
+                let event = unsafe {
+                    std::ptr::read_unaligned(
+                        buffer.as_ptr().add(offset) as *const libc::inotify_event
+                    )
+                };
+                // The name is padded with null bytes
+                let name = &buffer[offset + header..offset + header + event.len as usize];
+                let name = name.split(|b| *b == 0).next().unwrap_or_default();
+                if self
+                    .files
+                    .get(&event.wd)
+                    .is_some_and(|names| names.iter().any(|n| n.as_bytes() == name))
+                {
+                    changed = true;
+                }
+                offset += header + event.len as usize;
+            }
+        }
+
+        changed
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Sort `names` and join them with commas.
+fn sorted_names<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    let mut names = names.map(String::as_str).collect::<Vec<&str>>();
+    names.sort_unstable();
+    names.join(", ")
+}
+
+/// Summarize what changed between the configurations `old` and `new`, such as
+/// `aliases added: ll, la; prompt changed`.
+fn describe_changes(old: &mut ConfigFile, new: &mut ConfigFile) -> String {
+    let mut changes = Vec::new();
+
+    for kind in AliasKind::ALL.iter().copied() {
+        let old = kind.config_aliases(old);
+        let new = kind.config_aliases(new);
+        let added = sorted_names(new.keys().filter(|name| !old.contains_key(*name)));
+        let removed = sorted_names(old.keys().filter(|name| !new.contains_key(*name)));
+        let changed = sorted_names(
+            new.iter()
+                .filter(|(name, value)| old.get(*name).is_some_and(|v| v != *value))
+                .map(|(name, _)| name),
+        );
+        for (what, list) in [("added", added), ("removed", removed), ("changed", changed)] {
+            if !list.is_empty() {
+                changes.push(format!("{} {}: {}", kind.section(), what, list));
+            }
+        }
+    }
+    if old.abbreviations != new.abbreviations {
+        changes.push("abbreviations changed".to_string());
+    }
+    if old.prompt != new.prompt {
+        changes.push("prompt changed".to_string());
+    }
+
+    if changes.is_empty() {
+        "options changed".to_string()
+    } else {
+        changes.join("; ")
+    }
+}
+
+pub trait WatchConfig {
+    fn watch_config(&mut self);
+    fn reload_changed_config(&mut self);
+}
+
+impl WatchConfig for Osh {
+    /// Start watching the configuration files loaded, in both formats so switching from one to
+    /// the other is noticed.
+    fn watch_config(&mut self) {
+        let paths = config_layers(self.config_path.as_deref())
+            .into_iter()
+            .flat_map(|(path, _)| {
+                let yaml = path.with_extension("yaml");
+                let toml = path.with_extension("toml");
+                [path, yaml, toml]
+            })
+            .collect::<Vec<PathBuf>>();
+        self.config_watcher = ConfigWatcher::new(&paths);
+    }
+
+    /// Reload the configuration if one of its files changed, unless one of them is invalid.
+    fn reload_changed_config(&mut self) {
+        if !self.config_watcher.as_mut().is_some_and(|w| w.changed()) {
+            return;
+        }
+
+        let (mut config, errors) = ConfigFile::load(self.config_path.as_deref());
+        if !errors.is_empty() {
+            for error in errors {
+                werror!("{}", error);
+            }
+            werror!("Configuration changed but not reloaded, the previous one is kept");
+            return;
+        }
+        // Comments and settings applied already, e.g. by `alias --save`, are not worth a notice
+        if config == self.config {
+            return;
+        }
+
+        let changes = describe_changes(&mut self.config, &mut config);
+        self.apply_config(config);
+        winfo!("Configuration reloaded: {}", changes);
+    }
+}
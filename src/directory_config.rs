@@ -0,0 +1,305 @@
+use crate::change_directory::logical_current_dir;
+use crate::parameters::Parameters;
+use crate::shell::Osh;
+use crate::utils::Utils;
+use crate::{werror, winfo, wwarning};
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use console::style;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Files looked for in the current directory and its parents
+const FILE_NAMES: [&str; 2] = [".osh.yaml", ".oshenv"];
+
+/// Directories whose files may be loaded, along with the hash of the files allowed
+const TRUST_DATABASE: &str = "~/.osh_trust";
+
+/// Settings of a `.osh.yaml` file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LocalConfig {
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+}
+
+/// Parse the `NAME=VALUE` lines of a `.oshenv` file. Comments, blank lines and `export`
+/// prefixes are ignored, and values may be quoted.
+fn parse_env_file(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) if !name.is_empty() => (name, value),
+            _ => return Err(format!("line {}: expected NAME=VALUE", number + 1)),
+        };
+        let value = shell_words::split(value)
+            .map_err(|e| format!("line {}: {}", number + 1, e))?
+            .join(" ");
+        variables.push((name.to_string(), value));
+    }
+    Ok(variables)
+}
+
+/// Find the closest directory from `dir` up to the root holding configuration files, and
+/// return it with these files.
+fn find_directory_config(dir: &Path) -> Option<(PathBuf, Vec<PathBuf>)> {
+    dir.ancestors().find_map(|dir| {
+        let files = FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .filter(|file| file.is_file())
+            .collect::<Vec<PathBuf>>();
+        (!files.is_empty()).then(|| (dir.to_path_buf(), files))
+    })
+}
+
+/// Hash the names and contents of `files`, so any change requires trusting them again.
+fn hash_files(files: &[PathBuf]) -> Option<String> {
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(file.as_os_str().to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(file).ok()?);
+        hasher.update([0]);
+    }
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// Directories whose configuration files the user allowed to load, stored as `hash path`
+/// lines.
+struct TrustDatabase {
+    path: PathBuf,
+    entries: HashMap<PathBuf, String>,
+}
+
+impl TrustDatabase {
+    fn load() -> TrustDatabase {
+        let path = PathBuf::from(Osh::perform_expansion_on_single_element(TRUST_DATABASE));
+        let entries = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(hash, dir)| (PathBuf::from(dir), hash.to_string()))
+            .collect();
+        TrustDatabase { path, entries }
+    }
+
+    fn save(&self) -> bool {
+        let content = self
+            .entries
+            .iter()
+            .map(|(dir, hash)| format!("{} {}\n", hash, dir.display()))
+            .collect::<String>();
+        match fs::write(&self.path, content) {
+            Ok(()) => true,
+            Err(e) => {
+                werror!("Cannot save '{}': {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+
+    fn is_trusted(&self, dir: &Path, hash: &str) -> bool {
+        self.entries.get(dir).is_some_and(|h| h == hash)
+    }
+}
+
+/// Configuration files of a directory applied to the shell, and what they replaced.
+#[derive(Default)]
+pub struct DirectoryConfig {
+    /// Directory whose files are applied, and the hash of these files
+    applied: Option<(PathBuf, String)>,
+    /// Variables set, with their previous value
+    variables: Vec<(String, Option<OsString>)>,
+    /// Aliases defined, with their previous value
+    aliases: Vec<(String, Option<String>)>,
+    /// Hash of the untrusted files reported last, so they are reported only once
+    reported: Option<String>,
+}
+
+pub trait DirectoryConfiguration {
+    fn update_directory_config(&mut self);
+    fn revert_directory_config(&mut self);
+    fn apply_directory_config(&mut self, files: &[PathBuf]);
+    fn trust(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl DirectoryConfiguration for Osh {
+    /// Apply the `.osh.yaml` and `.oshenv` files of the current directory or of its closest
+    /// parent holding some, if they are trusted, after reverting the ones of the previous
+    /// directory. Called after changing directory and before every prompt, so edited files
+    /// are noticed.
+    fn update_directory_config(&mut self) {
+        let found = find_directory_config(&logical_current_dir())
+            .and_then(|(dir, files)| hash_files(&files).map(|hash| (dir, files, hash)));
+        let current = found
+            .as_ref()
+            .map(|(dir, _, hash)| (dir.clone(), hash.clone()));
+        if current == self.directory_config.applied {
+            return;
+        }
+
+        self.revert_directory_config();
+        let (dir, files, hash) = match found {
+            Some(f) => f,
+            None => return,
+        };
+
+        if !TrustDatabase::load().is_trusted(&dir, &hash) {
+            if self.directory_config.reported.as_ref() != Some(&hash) {
+                let files = files
+                    .iter()
+                    .map(|f| f.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                wwarning!("{} not trusted, run `trust` to load it", files);
+                self.directory_config.reported = Some(hash);
+            }
+            return;
+        }
+
+        // Invalid files are reported once, until they change
+        self.directory_config.applied = Some((dir, hash));
+        self.apply_directory_config(&files);
+    }
+
+    /// Restore the variables and aliases replaced by the files of the directory left.
+    fn revert_directory_config(&mut self) {
+        let config = std::mem::take(&mut self.directory_config);
+        for (name, value) in config.variables.into_iter().rev() {
+            match value {
+                Some(value) => self.set_variable(&name, value),
+                None => self.unset_variable(&name),
+            }
+        }
+        for (name, value) in config.aliases.into_iter().rev() {
+            match value {
+                Some(value) => self.aliases.insert(name, value),
+                None => self.aliases.remove(&name),
+            };
+        }
+        self.directory_config.reported = config.reported;
+    }
+
+    /// Set the variables and define the aliases of `files`, remembering what they replace.
+    /// Variables are expanded in values, e.g. `PATH=$PWD/bin:$PATH`.
+    fn apply_directory_config(&mut self, files: &[PathBuf]) {
+        let mut variables = Vec::new();
+        let mut aliases = HashMap::new();
+        for file in files {
+            let content = match fs::read_to_string(file) {
+                Ok(c) => c,
+                Err(e) => {
+                    werror!("Cannot read '{}': {}", file.display(), e);
+                    return;
+                }
+            };
+            let result = if file.ends_with(".oshenv") {
+                parse_env_file(&content).map(|env| variables.extend(env))
+            } else {
+                serde_yaml::from_str::<Option<LocalConfig>>(&content)
+                    .map(|config| {
+                        let config = config.unwrap_or_default();
+                        variables.extend(config.env);
+                        aliases.extend(config.aliases);
+                    })
+                    .map_err(|e| e.to_string())
+            };
+            if let Err(e) = result {
+                werror!("{}: {}", file.display(), e);
+                return;
+            }
+        }
+
+        for (name, value) in variables {
+            let value = Osh::perform_expansion_on_single_element(&value);
+            self.directory_config
+                .variables
+                .push((name.clone(), env::var_os(&name)));
+            self.set_variable(&name, value);
+        }
+        for (name, value) in aliases {
+            let previous = self.aliases.insert(name.clone(), value);
+            self.directory_config.aliases.push((name, previous));
+        }
+
+        let files = files
+            .iter()
+            .map(|f| f.display().to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        winfo!("Loaded {}", files);
+    }
+
+    /// Implementation of the `trust` builtin, which allows the `.osh.yaml` and `.oshenv` files
+    /// of a directory to be loaded. Trusting is required again whenever the files change.
+    ///
+    /// - `trust [DIR]`: trust the files of `DIR`, or of the current directory, as they are
+    /// - `trust -r [DIR]`: revoke the trust
+    /// - `trust -l`: list the trusted directories
+    fn trust(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut database = TrustDatabase::load();
+        let (revoke, args) = match args.split_first() {
+            Some((flag, _)) if flag == "-l" || flag == "--list" => {
+                let mut dirs = database.entries.keys().collect::<Vec<&PathBuf>>();
+                dirs.sort();
+                for dir in dirs {
+                    if let Err(e) = writeln!(output, "{}", dir.display()) {
+                        werror!("trust: write error: {}", e);
+                        return 1;
+                    }
+                }
+                return 0;
+            }
+            Some((flag, rest)) if flag == "-r" || flag == "--revoke" => (true, rest),
+            _ => (false, args),
+        };
+
+        let start = match args.first() {
+            Some(dir) => logical_current_dir().join(Osh::perform_expansion_on_single_element(dir)),
+            None => logical_current_dir(),
+        };
+        let (dir, files) = match find_directory_config(&start) {
+            Some(found) => found,
+            None => {
+                werror!("trust: no .osh.yaml or .oshenv file found");
+                return 1;
+            }
+        };
+
+        if revoke {
+            database.entries.remove(&dir);
+        } else {
+            match hash_files(&files) {
+                Some(hash) => database.entries.insert(dir, hash),
+                None => {
+                    werror!("trust: cannot read the files of '{}'", dir.display());
+                    return 1;
+                }
+            };
+        }
+        if !database.save() {
+            return 1;
+        }
+
+        // Files applied already must be reverted when their trust is revoked
+        if revoke {
+            self.revert_directory_config();
+        }
+        self.update_directory_config();
+        0
+    }
+}
@@ -0,0 +1,246 @@
+use crate::change_directory::{logical_current_dir, ChangeDirectory};
+use crate::shell::Osh;
+use crate::werror;
+
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use console::style;
+
+/// Check if `arg` designates an entry of the directory stack: `+N` or `-N`.
+pub fn is_stack_index(arg: &str) -> bool {
+    arg.len() > 1
+        && (arg.starts_with('+') || arg.starts_with('-'))
+        && arg[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Replace the home directory with `~` in `path`.
+pub fn tilde_path(path: &Path) -> String {
+    let path = path.display().to_string();
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && path.starts_with(&home) => {
+            format!("~{}", &path[home.len()..])
+        }
+        _ => path,
+    }
+}
+
+pub trait DirectoryStack {
+    fn directories(&self) -> Vec<PathBuf>;
+    fn stack_position(&self, index: &str, builtin: &str) -> Option<usize>;
+    fn rotate_directory_stack(&mut self, index: &str, builtin: &str) -> u32;
+    fn pushd(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn popd(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn dirs(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl DirectoryStack for Osh {
+    /// List the current directory followed by the directory stack, as displayed by `dirs`.
+    fn directories(&self) -> Vec<PathBuf> {
+        let cwd = logical_current_dir();
+        std::iter::once(cwd)
+            .chain(self.directory_stack.iter().cloned())
+            .collect()
+    }
+
+    /// Convert `+N` (counting from the left of `dirs`, starting at 0) or `-N` (counting from
+    /// the right) into a position in the list returned by `directories`.
+    fn stack_position(&self, index: &str, builtin: &str) -> Option<usize> {
+        let len = self.directory_stack.len() + 1;
+        let n = index[1..].parse::<usize>().ok().filter(|n| *n < len);
+        let position = match n {
+            Some(n) if index.starts_with('+') => Some(n),
+            Some(n) => Some(len - 1 - n),
+            None => None,
+        };
+
+        if position.is_none() {
+            werror!("{}: {}: directory stack index out of range", builtin, index);
+        }
+        position
+    }
+
+    /// Rotate the stack so the entry at `index` becomes the current directory.
+    fn rotate_directory_stack(&mut self, index: &str, builtin: &str) -> u32 {
+        let position = match self.stack_position(index, builtin) {
+            Some(p) => p,
+            None => return 1,
+        };
+
+        let mut directories = self.directories();
+        directories.rotate_left(position);
+        if !self.set_directory(&directories[0], false) {
+            return 1;
+        }
+        self.directory_stack = directories.split_off(1);
+        0
+    }
+
+    /// Implementation of the `pushd` builtin:
+    ///
+    /// - `pushd DIR`: push the current directory on the stack and go to `DIR`
+    /// - `pushd`: swap the current directory and the top of the stack
+    /// - `pushd +N` or `pushd -N`: rotate the stack, see `dirs -v` for indexes
+    /// - `-n`: only manipulate the stack, without changing directory
+    fn pushd(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let no_cd = args.first().is_some_and(|a| a == "-n");
+        let args = if no_cd { &args[1..] } else { args };
+
+        let status = match args.first() {
+            None => {
+                if self.directory_stack.is_empty() {
+                    werror!("pushd: no other directory");
+                    return 1;
+                }
+                let cwd = logical_current_dir();
+                let top = self.directory_stack.remove(0);
+                if !self.set_directory(&top, false) {
+                    self.directory_stack.insert(0, top);
+                    return 1;
+                }
+                self.directory_stack.insert(0, cwd);
+                0
+            }
+            Some(index) if is_stack_index(index) => self.rotate_directory_stack(index, "pushd"),
+            Some(dir) if no_cd => {
+                self.directory_stack.insert(0, PathBuf::from(dir));
+                0
+            }
+            Some(dir) => {
+                let cwd = logical_current_dir();
+                if !self.set_directory(Path::new(dir), false) {
+                    return 1;
+                }
+                self.directory_stack.insert(0, cwd);
+                0
+            }
+        };
+
+        if status == 0 {
+            self.dirs(&[], output)
+        } else {
+            status
+        }
+    }
+
+    /// Implementation of the `popd` builtin:
+    ///
+    /// - `popd`: remove the top of the stack and go there
+    /// - `popd +N` or `popd -N`: remove an entry of the stack, see `dirs -v` for indexes
+    /// - `-n`: only manipulate the stack, without changing directory
+    fn popd(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let no_cd = args.first().is_some_and(|a| a == "-n");
+        let args = if no_cd { &args[1..] } else { args };
+
+        if self.directory_stack.is_empty() {
+            werror!("popd: directory stack empty");
+            return 1;
+        }
+
+        let position = match args.first() {
+            None => 0,
+            Some(index) if is_stack_index(index) => match self.stack_position(index, "popd") {
+                Some(p) => p,
+                None => return 1,
+            },
+            Some(arg) => {
+                werror!("popd: {}: invalid argument", arg);
+                return 2;
+            }
+        };
+
+        if position == 0 && !no_cd {
+            // The current directory is removed by going to the top of the stack
+            let top = self.directory_stack[0].clone();
+            if !self.set_directory(&top, false) {
+                return 1;
+            }
+            self.directory_stack.remove(0);
+        } else {
+            // With -n, the current directory is kept so `popd -n` removes the top of the stack
+            self.directory_stack.remove(position.max(1) - 1);
+        }
+
+        self.dirs(&[], output)
+    }
+
+    /// Implementation of the `dirs` builtin:
+    ///
+    /// - `dirs`: display the current directory and the stack on a single line
+    /// - `-v`: display one entry per line, prefixed with its index
+    /// - `-p`: display one entry per line
+    /// - `-l`: do not replace the home directory with `~`
+    /// - `-c`: clear the stack
+    /// - `+N` or `-N`: only display the Nth entry
+    fn dirs(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut verbose = false;
+        let mut per_line = false;
+        let mut long = false;
+        let mut index = None;
+
+        for arg in args {
+            if is_stack_index(arg) {
+                index = Some(arg);
+                continue;
+            }
+            match arg.as_str() {
+                "-v" => verbose = true,
+                "-p" => per_line = true,
+                "-l" => long = true,
+                "-c" => {
+                    self.directory_stack.clear();
+                    return 0;
+                }
+                _ => {
+                    werror!("dirs: {}: invalid option", arg);
+                    return 2;
+                }
+            }
+        }
+
+        let mut directories = self
+            .directories()
+            .iter()
+            .map(|d| {
+                if long {
+                    d.display().to_string()
+                } else {
+                    tilde_path(d)
+                }
+            })
+            .enumerate()
+            .collect::<Vec<(usize, String)>>();
+
+        if let Some(index) = index {
+            match self.stack_position(index, "dirs") {
+                Some(p) => directories = vec![directories.swap_remove(p)],
+                None => return 1,
+            }
+        }
+
+        let result = if verbose {
+            directories
+                .iter()
+                .map(|(i, d)| format!("{:>2}  {}\n", i, d))
+                .collect::<String>()
+        } else if per_line {
+            directories
+                .iter()
+                .map(|(_, d)| format!("{}\n", d))
+                .collect::<String>()
+        } else {
+            let line = directories
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect::<Vec<String>>();
+            format!("{}\n", line.join(" "))
+        };
+
+        if let Err(e) = output.write_all(result.as_bytes()) {
+            werror!("dirs: write error: {}", e);
+            return 1;
+        }
+        0
+    }
+}
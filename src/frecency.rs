@@ -0,0 +1,274 @@
+use crate::change_directory::{logical_current_dir, normalize, ChangeDirectory};
+use crate::directory_stack::tilde_path;
+use crate::shell::Osh;
+use crate::werror;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use console::style;
+use skim::prelude::*;
+
+/// Once the sum of every rank exceeds this value, ranks are aged so old directories are
+/// eventually forgotten
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+/// Entries whose rank falls below this value after aging are removed
+const MIN_RANK: f64 = 1.0;
+
+/// When the best match does not score at least this many times higher than the second one,
+/// the user is asked to pick the directory
+const AMBIGUITY_RATIO: f64 = 2.0;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Directories visited with `cd`, ranked by frequency and recency like `z` does.
+/// Each line of the database is stored as `path|rank|last access timestamp`.
+pub struct FrecencyDatabase {
+    path: PathBuf,
+    entries: HashMap<PathBuf, (f64, u64)>,
+}
+
+impl FrecencyDatabase {
+    /// Load the database stored at `path`. Invalid lines are ignored.
+    pub fn load(path: PathBuf) -> FrecencyDatabase {
+        let mut entries = HashMap::new();
+
+        if let Ok(content) = fs::read_to_string(&path) {
+            for line in content.lines() {
+                let mut fields = line.rsplitn(3, '|');
+                let time = fields.next().and_then(|t| t.parse::<u64>().ok());
+                let rank = fields.next().and_then(|r| r.parse::<f64>().ok());
+                if let (Some(time), Some(rank), Some(dir)) = (time, rank, fields.next()) {
+                    entries.insert(PathBuf::from(dir), (rank, time));
+                }
+            }
+        }
+
+        FrecencyDatabase { path, entries }
+    }
+
+    fn save(&self) {
+        let content = self
+            .entries
+            .iter()
+            .map(|(dir, (rank, time))| format!("{}|{}|{}\n", dir.display(), rank, time))
+            .collect::<String>();
+        if let Err(e) = fs::write(&self.path, content) {
+            werror!("Cannot save '{}': {}", self.path.display(), e);
+        }
+    }
+
+    /// Record a visit of `dir` and save the database.
+    pub fn add(&mut self, dir: &Path) {
+        let entry = self.entries.entry(dir.to_path_buf()).or_insert((0.0, 0));
+        entry.0 += 1.0;
+        entry.1 = now();
+
+        let total = self.entries.values().map(|(rank, _)| rank).sum::<f64>();
+        if total > MAX_TOTAL_RANK {
+            for (rank, _) in self.entries.values_mut() {
+                *rank *= 0.99;
+            }
+            self.entries.retain(|_, (rank, _)| *rank >= MIN_RANK);
+        }
+
+        self.save();
+    }
+
+    /// Forget `dir`, used for directories that do not exist anymore.
+    pub fn remove(&mut self, dir: &Path) {
+        if self.entries.remove(dir).is_some() {
+            self.save();
+        }
+    }
+
+    /// List directories matching every fragment, best score first.
+    /// Fragments must appear in order in the path. The search ignores case unless a fragment
+    /// has an uppercase character.
+    pub fn matches(&self, fragments: &[String]) -> Vec<(f64, PathBuf)> {
+        let now = now();
+        let ignore_case = fragments
+            .iter()
+            .all(|f| !f.chars().any(|c| c.is_uppercase()));
+        let normalize = |s: &str| {
+            if ignore_case {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            }
+        };
+        let fragments = fragments
+            .iter()
+            .map(|f| normalize(f))
+            .collect::<Vec<String>>();
+
+        let mut matches = self
+            .entries
+            .iter()
+            .filter(|(dir, _)| {
+                let path = normalize(&dir.display().to_string());
+                let mut rest = &path[..];
+                for fragment in fragments.iter() {
+                    match rest.find(fragment.as_str()) {
+                        Some(i) => rest = &rest[i + fragment.len()..],
+                        None => return false,
+                    }
+                }
+                true
+            })
+            .map(|(dir, (rank, time))| (frecency(*rank, *time, now), dir.clone()))
+            .collect::<Vec<(f64, PathBuf)>>();
+
+        matches.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        matches
+    }
+}
+
+/// Weight `rank` according to the time elapsed since the directory was last visited.
+fn frecency(rank: f64, time: u64, now: u64) -> f64 {
+    let elapsed = now.saturating_sub(time);
+    if elapsed < 3600 {
+        rank * 4.0
+    } else if elapsed < 86400 {
+        rank * 2.0
+    } else if elapsed < 604800 {
+        rank / 2.0
+    } else {
+        rank / 4.0
+    }
+}
+
+/// Let the user choose among `directories` with skim.
+fn pick_directory(directories: &[PathBuf]) -> Option<PathBuf> {
+    let options = SkimOptionsBuilder::default()
+        .height(Some("30%"))
+        .reverse(true)
+        .build()
+        .unwrap();
+    let candidates = directories
+        .iter()
+        .map(|d| d.display().to_string())
+        .collect::<Vec<String>>();
+    let item_reader = SkimItemReader::default();
+    let items = item_reader.of_bufread(Cursor::new(candidates.join("\n")));
+
+    let output = Skim::run_with(&options, Some(items))?;
+    if output.is_abort {
+        return None;
+    }
+    output
+        .selected_items
+        .first()
+        .map(|item| PathBuf::from(item.output().to_string()))
+}
+
+pub trait Frecency {
+    fn z(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl Frecency for Osh {
+    /// Implementation of the `z` builtin, to jump to a directory visited before:
+    ///
+    /// - `z FRAGMENT...`: go to the best ranked directory matching every fragment, or let the
+    ///   user choose when several directories rank closely
+    /// - `z -i FRAGMENT...`: always let the user choose among the matching directories
+    /// - `z -l [FRAGMENT...]`: list matching directories along with their score
+    /// - `z -x [DIR]`: forget the current directory, or `DIR`
+    fn z(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut interactive = false;
+        let mut list = false;
+        let mut fragments = args;
+
+        while let Some((flag, rest)) = fragments.split_first() {
+            match flag.as_str() {
+                "-i" => interactive = true,
+                "-l" => list = true,
+                "-x" => {
+                    // Entries are the paths visited by `cd`, symlinks included
+                    let dir = match rest.first() {
+                        Some(d) => normalize(&logical_current_dir().join(d)),
+                        None => logical_current_dir(),
+                    };
+                    self.frecency.remove(&dir);
+                    return 0;
+                }
+                "--" => {
+                    fragments = rest;
+                    break;
+                }
+                f if f.starts_with('-') && f.len() > 1 => {
+                    werror!("z: {}: invalid option", f);
+                    return 2;
+                }
+                _ => break,
+            }
+            fragments = rest;
+        }
+
+        // Directories may have been removed since they were visited
+        let mut matches = self.frecency.matches(fragments);
+        for (_, dir) in matches.iter().filter(|(_, dir)| !dir.is_dir()) {
+            self.frecency.remove(dir);
+        }
+        matches.retain(|(_, dir)| dir.is_dir());
+
+        if list {
+            for (score, dir) in matches.iter().rev() {
+                if let Err(e) = writeln!(output, "{:<10.1} {}", score, tilde_path(dir)) {
+                    werror!("z: write error: {}", e);
+                    return 1;
+                }
+            }
+            return 0;
+        }
+
+        if fragments.is_empty() && !interactive {
+            werror!("z: usage: z [-i] [-l] [-x] FRAGMENT...");
+            return 2;
+        }
+
+        let target = match &matches[..] {
+            [] => {
+                werror!("z: {}: no matching directory", fragments.join(" "));
+                return 1;
+            }
+            [(_, dir)] if !interactive => dir.clone(),
+            [(best, dir), (second, _), ..]
+                if !interactive && *best >= *second * AMBIGUITY_RATIO =>
+            {
+                dir.clone()
+            }
+            _ => {
+                // Nobody can answer the picker outside of a terminal
+                let is_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+                if !is_tty {
+                    matches[0].1.clone()
+                } else {
+                    let directories = matches
+                        .into_iter()
+                        .map(|(_, dir)| dir)
+                        .collect::<Vec<PathBuf>>();
+                    match pick_directory(&directories) {
+                        Some(dir) => dir,
+                        None => return 1,
+                    }
+                }
+            }
+        };
+
+        if !self.set_directory(&target, false) {
+            return 1;
+        }
+        self.frecency.add(&target);
+        0
+    }
+}
@@ -0,0 +1,317 @@
+use crate::builtins::{InputStream, Streams};
+use crate::parameters::{is_valid_name, Parameters};
+use crate::shell::Osh;
+use crate::werror;
+
+use std::env;
+use std::io::{ErrorKind, Read};
+use std::time::{Duration, Instant};
+
+use console::style;
+
+/// Default value of `IFS` when the variable is not set.
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Status returned by `read` when the timeout expires, as bash does.
+const TIMEOUT_STATUS: u32 = 142;
+
+#[derive(Default)]
+struct ReadOptions {
+    raw: bool,
+    silent: bool,
+    prompt: Option<String>,
+    timeout: Option<Duration>,
+    max_chars: Option<usize>,
+    delimiter: Option<u8>,
+    array: Option<String>,
+    names: Vec<String>,
+}
+
+enum ReadResult {
+    /// The delimiter or the maximum number of characters has been reached
+    Complete,
+    Eof,
+    Timeout,
+}
+
+pub trait Input {
+    fn read(&mut self, args: &[String], streams: &mut Streams) -> u32;
+}
+
+impl Input for Osh {
+    /// Implementation of the `read` builtin:
+    /// `read [-r] [-s] [-p prompt] [-t timeout] [-n N] [-d delim] [-a array] [NAME...]`
+    ///
+    /// A line is read from the standard input of the builtin, split according to `IFS` and each field is
+    /// assigned to a `NAME`, the last one receiving the remaining of the line. The line is
+    /// stored in `REPLY` when no name is supplied.
+    fn read(&mut self, args: &[String], streams: &mut Streams) -> u32 {
+        let options = match parse_options(args) {
+            Ok(o) => o,
+            Err(e) => {
+                werror!("read: {}", e);
+                return 2;
+            }
+        };
+
+        let names = options.names.iter().chain(options.array.iter());
+        if let Some(name) = names.into_iter().find(|n| !is_valid_name(n)) {
+            werror!("read: '{}': not a valid identifier", name);
+            return 1;
+        }
+
+        // The prompt is only relevant when a human is typing
+        let is_tty = streams.stdin.is_terminal();
+        if let Some(prompt) = &options.prompt {
+            if is_tty {
+                let _ = write!(streams.stderr, "{}", prompt);
+                let _ = streams.stderr.flush();
+            }
+        }
+
+        let echo_guard = if options.silent && is_tty {
+            EchoGuard::disable_echo()
+        } else {
+            None
+        };
+        let (line, result) = read_line(streams.stdin, &options);
+        drop(echo_guard);
+        if options.silent && is_tty {
+            let _ = writeln!(streams.stderr);
+        }
+
+        if let ReadResult::Timeout = result {
+            return TIMEOUT_STATUS;
+        }
+
+        let ifs = env::var("IFS").unwrap_or_else(|_| DEFAULT_IFS.into());
+        if let Some(array) = &options.array {
+            self.arrays
+                .insert(array.clone(), split_fields(&line, &ifs, usize::MAX));
+        } else if options.names.is_empty() {
+            self.set_variable("REPLY", &line);
+        } else {
+            let mut fields = split_fields(&line, &ifs, options.names.len()).into_iter();
+            for name in options.names.iter() {
+                self.set_variable(name, fields.next().unwrap_or_default());
+            }
+        }
+
+        match result {
+            ReadResult::Complete => 0,
+            _ => 1,
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<ReadOptions, String> {
+    let mut options = ReadOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            options.names.extend(args.cloned());
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() == 1 {
+            options.names.push(arg.clone());
+            options.names.extend(args.cloned());
+            break;
+        }
+
+        let flags = &arg[1..];
+        for (i, flag) in flags.char_indices() {
+            match flag {
+                'r' => options.raw = true,
+                's' => options.silent = true,
+                'p' | 't' | 'n' | 'd' | 'a' => {
+                    // The value is either the rest of the argument or the next argument
+                    let rest = &flags[i + flag.len_utf8()..];
+                    let value = if !rest.is_empty() {
+                        rest.to_string()
+                    } else {
+                        match args.next() {
+                            Some(v) => v.clone(),
+                            None => return Err(format!("-{}: option requires an argument", flag)),
+                        }
+                    };
+
+                    match flag {
+                        'p' => options.prompt = Some(value),
+                        't' => {
+                            let seconds = value
+                                .parse::<f64>()
+                                .map_err(|_| format!("{}: invalid timeout specification", value))?;
+                            options.timeout = Some(Duration::from_secs_f64(seconds.max(0.0)));
+                        }
+                        'n' => {
+                            options.max_chars = Some(
+                                value
+                                    .parse::<usize>()
+                                    .map_err(|_| format!("{}: invalid number", value))?,
+                            )
+                        }
+                        // An empty delimiter means NUL, like bash
+                        'd' => options.delimiter = Some(value.bytes().next().unwrap_or(0)),
+                        _ => options.array = Some(value),
+                    }
+                    break;
+                }
+                _ => return Err(format!("-{}: invalid option", flag)),
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+/// Read a single byte from `input`, waiting at most until `deadline`.
+/// Bytes are read one at a time so the data following the line is left for the next
+/// command sharing the same input.
+fn read_byte(input: &mut InputStream, deadline: Option<Instant>) -> Result<Option<u8>, ReadResult> {
+    if let Some(deadline) = deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !input.wait_readable(remaining) {
+            return Err(ReadResult::Timeout);
+        }
+    }
+
+    let mut byte = [0u8];
+    loop {
+        match input.read(&mut byte) {
+            Ok(1) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            _ => return Ok(None),
+        }
+    }
+}
+
+fn read_line(input: &mut InputStream, options: &ReadOptions) -> (String, ReadResult) {
+    let delimiter = options.delimiter.unwrap_or(b'\n');
+    let deadline = options.timeout.map(|t| Instant::now() + t);
+    let mut bytes = Vec::new();
+    let mut chars = 0;
+    let mut escaped = false;
+
+    let result = loop {
+        if options.max_chars.is_some_and(|max| chars >= max) {
+            break ReadResult::Complete;
+        }
+
+        let byte = match read_byte(input, deadline) {
+            Ok(Some(b)) => b,
+            Ok(None) => break ReadResult::Eof,
+            Err(e) => break e,
+        };
+
+        if escaped {
+            escaped = false;
+            // A backslash followed by a newline continues the line
+            if byte != b'\n' {
+                bytes.push(byte);
+                chars += 1;
+            }
+            continue;
+        }
+        if byte == b'\\' && !options.raw {
+            escaped = true;
+            continue;
+        }
+        if byte == delimiter {
+            break ReadResult::Complete;
+        }
+
+        bytes.push(byte);
+        // Only count the first byte of UTF-8 sequences
+        if byte & 0xc0 != 0x80 {
+            chars += 1;
+        }
+    };
+
+    (String::from_utf8_lossy(&bytes).to_string(), result)
+}
+
+/// Split `line` in at most `max` fields using the characters of `ifs` as separators.
+/// Whitespace separators are collapsed and trimmed, other separators delimit a field each.
+/// The last field holds the remaining of the line.
+fn split_fields(line: &str, ifs: &str, max: usize) -> Vec<String> {
+    let is_ifs_whitespace = |c: char| ifs.contains(c) && c.is_whitespace();
+    let mut fields = Vec::new();
+    let mut rest = line.trim_matches(is_ifs_whitespace);
+
+    while !rest.is_empty() {
+        if fields.len() + 1 == max {
+            fields.push(rest.to_string());
+            break;
+        }
+
+        match rest.find(|c: char| ifs.contains(c)) {
+            Some(i) => {
+                fields.push(rest[..i].to_string());
+                rest = rest[i..].trim_start_matches(is_ifs_whitespace);
+                // Skip a single non-whitespace separator
+                if let Some(c) = rest.chars().next() {
+                    if ifs.contains(c) && !c.is_whitespace() {
+                        rest = rest[c.len_utf8()..].trim_start_matches(is_ifs_whitespace);
+                    }
+                }
+            }
+            None => {
+                fields.push(rest.to_string());
+                break;
+            }
+        }
+    }
+
+    fields
+}
+
+/// Disable terminal echo for as long as the guard is alive.
+struct EchoGuard {
+    termios: libc::termios,
+}
+
+impl EchoGuard {
+    fn disable_echo() -> Option<EchoGuard> {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return None;
+        }
+
+        let mut silent = termios;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent) };
+
+        Some(EchoGuard { termios })
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.termios) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_split_like_posix() {
+        let cases: &[(&str, &str, usize, &[&str])] = &[
+            ("  a  b\tc  ", " \t\n", usize::MAX, &["a", "b", "c"]),
+            ("a b  c d ", " \t\n", 2, &["a", "b  c d"]),
+            ("a", " \t\n", 3, &["a"]),
+            ("   ", " \t\n", usize::MAX, &[]),
+            ("a:b::c", ":", usize::MAX, &["a", "b", "", "c"]),
+            ("a:b:", ":", usize::MAX, &["a", "b"]),
+            ("a : b ,c", " :,", usize::MAX, &["a", "b", "c"]),
+            (":a", ":", usize::MAX, &["", "a"]),
+            ("a b", "", usize::MAX, &["a b"]),
+        ];
+
+        for (line, ifs, max, expected) in cases {
+            assert_eq!(split_fields(line, ifs, *max), *expected, "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use crate::shell::Osh;
+use crate::trap::{signal_name, signal_number, SIGNALS};
+use crate::{werror, winfo};
+
+use std::io::{self, Write};
+use std::process::Child;
+
+use console::style;
+
+/// Commands run in background with `&`, waited for by `wait` or before the next prompt.
+pub struct Job {
+    /// Number used to refer to the job as `%N`
+    pub id: usize,
+    /// Command line of the job, as displayed by `jobs`
+    pub command: String,
+    /// Processes of the pipeline, along with their status once they exited
+    processes: Vec<(Child, Option<u32>)>,
+    /// Process group of the pipeline, whose ID is the one of its first process
+    pgid: u32,
+}
+
+impl Job {
+    pub fn new(id: usize, command: String, processes: Vec<Child>) -> Job {
+        Job {
+            id,
+            command,
+            pgid: processes.first().map_or(0, |p| p.id()),
+            processes: processes.into_iter().map(|p| (p, None)).collect(),
+        }
+    }
+
+    /// PIDs of the processes of the job.
+    pub fn pids(&self) -> Vec<u32> {
+        self.processes.iter().map(|(p, _)| p.id()).collect()
+    }
+
+    /// Status of the job once every process exited: the one of the last process, like for
+    /// pipelines run in foreground.
+    pub fn status(&self) -> Option<u32> {
+        if self.processes.iter().all(|(_, status)| status.is_some()) {
+            self.processes.last().and_then(|(_, status)| *status)
+        } else {
+            None
+        }
+    }
+
+    /// Collect the status of the processes which exited, without blocking.
+    pub fn poll(&mut self) -> Option<u32> {
+        for (process, status) in self.processes.iter_mut().filter(|(_, s)| s.is_none()) {
+            if let Ok(Some(exit_status)) = process.try_wait() {
+                *status = Some(Osh::exit_code(exit_status));
+            }
+        }
+        self.status()
+    }
+
+    /// Wait for the process `pid` of the job, or for every process if `pid` is not set.
+    /// Return the status of the process, or of the job.
+    pub fn wait(&mut self, pid: Option<u32>) -> u32 {
+        for (process, status) in self.processes.iter_mut() {
+            if status.is_some() || pid.is_some_and(|pid| pid != process.id()) {
+                continue;
+            }
+            *status = Some(match process.wait() {
+                Ok(exit_status) => Osh::exit_code(exit_status),
+                Err(e) => {
+                    werror!("wait: {}: {}", process.id(), e);
+                    127
+                }
+            });
+        }
+
+        match pid {
+            Some(pid) => self
+                .processes
+                .iter()
+                .find(|(p, _)| p.id() == pid)
+                .and_then(|(_, status)| *status)
+                .unwrap_or(127),
+            None => self.status().unwrap_or(127),
+        }
+    }
+
+    /// State of the job as displayed by `jobs`: `Running`, `Done` or `Exit N`.
+    fn state(&self) -> String {
+        match self.status() {
+            None => "Running".into(),
+            Some(0) => "Done".into(),
+            Some(status) => format!("Exit {}", status),
+        }
+    }
+}
+
+/// Parse a signal given to `kill` by name, such as `TERM` or `SIGTERM`, or by number.
+/// `0` only checks that the process exists.
+fn parse_signal(name: &str) -> Option<i32> {
+    if name == "0" {
+        return Some(0);
+    }
+    signal_number(name)
+}
+
+pub trait Jobs {
+    fn add_job(&mut self, command: String, processes: Vec<Child>) -> usize;
+    fn find_job(&self, spec: &str, builtin: &str) -> Option<usize>;
+    fn notify_jobs(&mut self);
+    fn jobs(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn kill(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn list_signals(&self, args: &[String], output: &mut dyn Write) -> u32;
+    fn wait(&mut self, args: &[String]) -> u32;
+}
+
+impl Jobs for Osh {
+    /// Register the processes of a pipeline run in background and return the number of the
+    /// new job.
+    fn add_job(&mut self, command: String, processes: Vec<Child>) -> usize {
+        let id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        self.last_background_pid = processes.last().map(|p| p.id());
+        self.jobs.push(Job::new(id, command, processes));
+        id
+    }
+
+    /// Find the position in the job table of the job designated by `spec`:
+    ///
+    /// - `%N`: job number `N`
+    /// - `%%`, `%+` or `%`: current job, the last one started
+    /// - `%-`: previous job
+    /// - `%STRING`: job whose command starts with `STRING`
+    /// - `%?STRING`: job whose command contains `STRING`
+    fn find_job(&self, spec: &str, builtin: &str) -> Option<usize> {
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let position = match name {
+            "" | "%" | "+" => self.jobs.len().checked_sub(1),
+            "-" => self.jobs.len().checked_sub(2),
+            n if n.chars().all(|c| c.is_ascii_digit()) => {
+                let id = n.parse::<usize>().ok();
+                self.jobs.iter().position(|j| Some(j.id) == id)
+            }
+            _ => {
+                let matches = self
+                    .jobs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, j)| match name.strip_prefix('?') {
+                        Some(fragment) => j.command.contains(fragment),
+                        None => j.command.starts_with(name),
+                    })
+                    .map(|(i, _)| i)
+                    .collect::<Vec<usize>>();
+                if matches.len() > 1 {
+                    werror!("{}: {}: ambiguous job spec", builtin, spec);
+                    return None;
+                }
+                matches.first().copied()
+            }
+        };
+
+        if position.is_none() {
+            werror!("{}: {}: no such job", builtin, spec);
+        }
+        position
+    }
+
+    /// Report the jobs which are done since the last prompt and forget about them.
+    fn notify_jobs(&mut self) {
+        let mut i = 0;
+        while i < self.jobs.len() {
+            if self.jobs[i].poll().is_none() {
+                i += 1;
+                continue;
+            }
+            let job = self.jobs.remove(i);
+            winfo!("[{}]   {:<24}{}", job.id, job.state(), job.command);
+        }
+    }
+
+    /// Implementation of the `jobs` builtin: list the jobs run in background, or the ones
+    /// designated by the job specs supplied.
+    ///
+    /// - `-l`: display the PIDs of the processes too
+    /// - `-p`: display the PIDs of the processes only
+    fn jobs(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut long = false;
+        let mut pids_only = false;
+        let mut args = args;
+        while let Some((flag, rest)) = args.split_first() {
+            match flag.as_str() {
+                "-l" => long = true,
+                "-p" => pids_only = true,
+                "--" => {
+                    args = rest;
+                    break;
+                }
+                f if f.starts_with('-') && f.len() > 1 => {
+                    werror!("jobs: {}: invalid option", f);
+                    return 2;
+                }
+                _ => break,
+            }
+            args = rest;
+        }
+
+        let mut status = 0;
+        let positions = if args.is_empty() {
+            (0..self.jobs.len()).collect()
+        } else {
+            let mut positions = Vec::new();
+            for spec in args {
+                match self.find_job(spec, "jobs") {
+                    Some(position) => positions.push(position),
+                    None => status = 1,
+                }
+            }
+            positions
+        };
+
+        let count = self.jobs.len();
+        for position in positions.iter().copied() {
+            let job = &mut self.jobs[position];
+            job.poll();
+
+            let marker = match count - position {
+                1 => '+',
+                2 => '-',
+                _ => ' ',
+            };
+            let pids = job
+                .pids()
+                .iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+            let result = if pids_only {
+                writeln!(output, "{}", pids.replace(' ', "\n"))
+            } else if long {
+                writeln!(
+                    output,
+                    "[{}]{} {} {:<24}{}",
+                    job.id,
+                    marker,
+                    pids,
+                    job.state(),
+                    job.command
+                )
+            } else {
+                writeln!(
+                    output,
+                    "[{}]{}  {:<24}{}",
+                    job.id,
+                    marker,
+                    job.state(),
+                    job.command
+                )
+            };
+            if let Err(e) = result {
+                werror!("jobs: write error: {}", e);
+                return 1;
+            }
+        }
+
+        // Jobs reported as done are not displayed again
+        let mut positions = positions;
+        positions.sort_unstable();
+        positions.dedup();
+        for position in positions.into_iter().rev() {
+            if self.jobs[position].status().is_some() {
+                self.jobs.remove(position);
+            }
+        }
+        status
+    }
+
+    /// Implementation of the `kill` builtin: send a signal, `TERM` by default, to processes
+    /// or to every process of jobs.
+    ///
+    /// - `kill [-s SIGNAL | -n NUMBER | -SIGNAL] PID|%JOB...`
+    /// - `kill -l [SIGNAL|STATUS...]`: list signal names, or convert signal numbers and exit
+    ///   statuses of processes killed by a signal to signal names, and names to numbers
+    fn kill(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut signal = libc::SIGTERM;
+        let mut args = args;
+        match args.split_first() {
+            Some((flag, rest)) if flag == "-l" || flag == "-L" => {
+                return self.list_signals(rest, output)
+            }
+            Some((flag, rest)) if flag == "-s" || flag == "-n" => match rest.split_first() {
+                Some((name, rest)) => {
+                    signal = match parse_signal(name) {
+                        Some(s) => s,
+                        None => {
+                            werror!("kill: {}: invalid signal specification", name);
+                            return 1;
+                        }
+                    };
+                    args = rest;
+                }
+                None => {
+                    werror!("kill: {}: option requires an argument", flag);
+                    return 2;
+                }
+            },
+            Some((flag, rest)) if flag == "--" => args = rest,
+            Some((flag, rest)) if flag.starts_with('-') && flag.len() > 1 => {
+                signal = match parse_signal(&flag[1..]) {
+                    Some(s) => s,
+                    None => {
+                        werror!("kill: {}: invalid signal specification", &flag[1..]);
+                        return 1;
+                    }
+                };
+                args = rest;
+            }
+            _ => (),
+        }
+        if let Some(("--", rest)) = args.split_first().map(|(f, r)| (f.as_str(), r)) {
+            args = rest;
+        }
+
+        if args.is_empty() {
+            werror!("kill: usage: kill [-s SIGNAL | -n NUMBER | -SIGNAL] PID | %JOB...");
+            return 2;
+        }
+
+        let mut status = 0;
+        for target in args {
+            let pids = if target.starts_with('%') {
+                match self.find_job(target, "kill") {
+                    Some(position) => {
+                        let job = &mut self.jobs[position];
+                        job.poll();
+                        // The whole process group is signaled, unless every process exited
+                        // and its ID may have been reused
+                        if job.processes.iter().all(|(_, status)| status.is_some()) {
+                            werror!("kill: {}: job has terminated", target);
+                            status = 1;
+                            continue;
+                        }
+                        vec![-(job.pgid as libc::pid_t)]
+                    }
+                    None => {
+                        status = 1;
+                        continue;
+                    }
+                }
+            } else {
+                match target.parse::<u32>() {
+                    Ok(pid) => vec![pid as libc::pid_t],
+                    Err(_) => {
+                        werror!("kill: {}: arguments must be process or job IDs", target);
+                        status = 1;
+                        continue;
+                    }
+                }
+            };
+
+            for pid in pids {
+                if unsafe { libc::kill(pid, signal) } != 0 {
+                    werror!("kill: ({}) - {}", pid, io::Error::last_os_error());
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// Implementation of `kill -l`. Statuses greater than 128 designate processes killed by
+    /// a signal.
+    fn list_signals(&self, args: &[String], output: &mut dyn Write) -> u32 {
+        if args.is_empty() {
+            for (name, number) in SIGNALS.iter() {
+                if let Err(e) = writeln!(output, "{:>2}) SIG{}", number, name) {
+                    werror!("kill: write error: {}", e);
+                    return 1;
+                }
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let converted = match arg.parse::<i32>() {
+                Ok(n) => signal_name(if n > 128 { n - 128 } else { n }).map(String::from),
+                Err(_) => signal_number(arg).map(|n| n.to_string()),
+            };
+            match converted {
+                Some(converted) => {
+                    if let Err(e) = writeln!(output, "{}", converted) {
+                        werror!("kill: write error: {}", e);
+                        return 1;
+                    }
+                }
+                None => {
+                    werror!("kill: {}: invalid signal specification", arg);
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// Implementation of the `wait` builtin: wait for the jobs or processes supplied, or for
+    /// every job, and return the status of the last one. Jobs waited for are forgotten.
+    fn wait(&mut self, args: &[String]) -> u32 {
+        if args.is_empty() {
+            for mut job in std::mem::take(&mut self.jobs) {
+                job.wait(None);
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for target in args {
+            let (position, pid) = if target.starts_with('%') {
+                match self.find_job(target, "wait") {
+                    Some(position) => (position, None),
+                    None => {
+                        status = 127;
+                        continue;
+                    }
+                }
+            } else {
+                let pid = match target.parse::<u32>() {
+                    Ok(pid) => pid,
+                    Err(_) => {
+                        werror!("wait: {}: not a pid or valid job spec", target);
+                        status = 2;
+                        continue;
+                    }
+                };
+                match self.jobs.iter().position(|j| j.pids().contains(&pid)) {
+                    Some(position) => (position, Some(pid)),
+                    None => {
+                        werror!("wait: pid {} is not a child of this shell", pid);
+                        status = 127;
+                        continue;
+                    }
+                }
+            };
+
+            status = self.jobs[position].wait(pid);
+            if self.jobs[position].status().is_some() {
+                self.jobs.remove(position);
+            }
+        }
+        status
+    }
+}
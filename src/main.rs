@@ -1,18 +1,58 @@
+mod abbreviation;
 mod alias;
+mod builtins;
+mod change_directory;
+mod command_not_found;
 mod config;
+mod config_watcher;
+mod directory_config;
+mod directory_stack;
+mod frecency;
+mod input;
+mod jobs;
 mod logging;
 mod macros;
+mod parameters;
+mod print;
+mod resolution;
+mod resources;
 mod rustyline_helper;
 mod shell;
+mod timing;
+mod trap;
 mod utils;
 
 use crate::logging::setup_logging;
 use crate::shell::Osh;
+use std::env;
+use std::path::PathBuf;
 extern crate shell_words;
 
+use console::style;
+
 fn main() -> rustyline::Result<()> {
     setup_logging();
 
-    let mut shell = Osh::new();
+    // `osh [--config FILE] [SCRIPT [ARGUMENTS...]]`
+    let mut args = env::args().collect::<Vec<String>>();
+    let mut config_path = None;
+    if args.get(1).map(String::as_str) == Some("--config") {
+        match args.get(2) {
+            Some(path) => config_path = Some(PathBuf::from(path)),
+            None => {
+                werror!("--config: option requires an argument");
+                std::process::exit(2);
+            }
+        }
+        args.drain(1..3);
+    }
+
+    let mut shell = Osh::new(config_path);
+
+    // Run the script supplied as first argument, if any, instead of an interactive session
+    if let Some(script) = args.get(1) {
+        std::process::exit(shell.run_script(script, &args[2..]) as i32);
+    }
+
     shell.repl()
 }
@@ -0,0 +1,175 @@
+use crate::shell::Osh;
+use crate::utils::Utils;
+use crate::werror;
+
+use std::env::{self, remove_var, set_var};
+use std::ffi::OsStr;
+use std::io::Write;
+
+use console::style;
+
+/// Check that `name` can be used as a variable name.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+pub trait Parameters {
+    fn lookup_parameter(&self, name: &str) -> Vec<String>;
+    fn lookup_array(&self, array: &str, index: &str) -> Vec<String>;
+    fn expand_parameters(&self, word: &str) -> Vec<String>;
+    fn set(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn shift(&mut self, args: &[String]) -> u32;
+    fn set_variable(&mut self, name: &str, value: impl AsRef<OsStr>);
+    fn unset_variable(&mut self, name: &str);
+}
+
+impl Parameters for Osh {
+    /// Resolve special parameters (`$?`, `$$`, `$!`, `$#`, `$0`, `$-`), positional parameters
+    /// (`$1`...) and environment variables.
+    ///
+    /// Since words are never split after expansion, `$@` always behaves like `"$@"`: each
+    /// positional parameter is kept as a separate word. `$*` joins them with the first
+    /// character of `IFS`.
+    fn lookup_parameter(&self, name: &str) -> Vec<String> {
+        match name {
+            "?" => vec![self.status.to_string()],
+            "$" => vec![std::process::id().to_string()],
+            "!" => self
+                .last_background_pid
+                .map(|pid| pid.to_string())
+                .into_iter()
+                .collect(),
+            "#" => vec![self.positional.len().to_string()],
+            "0" => vec![self.script_name.clone()],
+            // The only option flag so far
+            "-" => vec![if self.interactive { "i" } else { "" }.to_string()],
+            "@" => self.positional.clone(),
+            "*" => {
+                let separator = match env::var("IFS") {
+                    Ok(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    Err(_) => " ".into(),
+                };
+                vec![self.positional.join(&separator)]
+            }
+            _ => {
+                if let Some((array, index)) = name.strip_suffix(']').and_then(|n| n.split_once('['))
+                {
+                    return self.lookup_array(array, index);
+                }
+                match name.parse::<usize>() {
+                    // Such as `${00}`
+                    Ok(0) => vec![self.script_name.clone()],
+                    Ok(n) => self.positional.get(n - 1).cloned().into_iter().collect(),
+                    Err(_) => env::var(name).into_iter().collect(),
+                }
+            }
+        }
+    }
+
+    /// Resolve `${array[@]}`, `${array[*]}` and `${array[N]}`.
+    fn lookup_array(&self, array: &str, index: &str) -> Vec<String> {
+        let values = match self.arrays.get(array) {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+
+        match index {
+            "@" => values.clone(),
+            "*" => vec![values.join(" ")],
+            _ => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| values.get(i))
+                .cloned()
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Perform tilde and parameter expansion on a single word. The result may contain zero
+    /// or several words when `$@` is used.
+    fn expand_parameters(&self, word: &str) -> Vec<String> {
+        if !word.contains('$') {
+            return vec![Osh::perform_expansion_on_single_element(word)];
+        }
+
+        // `$@` without any positional parameter must not produce an empty argument
+        if (word == "$@" || word == "${@}") && self.positional.is_empty() {
+            return Vec::new();
+        }
+
+        Osh::perform_variable_expansion(word, &|name| self.lookup_parameter(name))
+    }
+
+    /// Implementation of the `set` builtin. Only the positional parameters can be set for now:
+    /// `set -- a b c` replaces them with `a`, `b` and `c`.
+    fn set(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        match args.split_first() {
+            None => {
+                let mut variables = env::vars().collect::<Vec<(String, String)>>();
+                variables.sort();
+                for (key, value) in variables {
+                    if let Err(e) = writeln!(output, "{}={}", key, shell_words::quote(&value)) {
+                        werror!("set: write error: {}", e);
+                        return 1;
+                    }
+                }
+            }
+            Some((first, rest)) if first == "--" => {
+                self.positional = rest.to_vec();
+            }
+            Some((first, _)) if first.starts_with('-') || first.starts_with('+') => {
+                werror!("set: {}: invalid option", first);
+                return 1;
+            }
+            Some(_) => {
+                self.positional = args.to_vec();
+            }
+        }
+        0
+    }
+
+    /// Implementation of the `shift` builtin: drop the `n` first positional parameters,
+    /// 1 by default.
+    fn shift(&mut self, args: &[String]) -> u32 {
+        let n = match args.first().map(|a| a.parse::<usize>()) {
+            None => 1,
+            Some(Ok(n)) => n,
+            Some(Err(_)) => {
+                werror!("shift: {}: numeric argument required", args[0]);
+                return 1;
+            }
+        };
+
+        if n > self.positional.len() {
+            werror!("shift: {}: shift count out of range", n);
+            return 1;
+        }
+
+        self.positional.drain(..n);
+        0
+    }
+
+    /// Set the environment variable `name` to `value`. Cached paths of commands are forgotten
+    /// when `PATH` changes, since they may not be the ones found first anymore.
+    fn set_variable(&mut self, name: &str, value: impl AsRef<OsStr>) {
+        set_var(name, value);
+        if name == "PATH" {
+            self.command_cache.borrow_mut().clear();
+        }
+    }
+
+    /// Remove the environment variable `name`, forgetting cached paths like `set_variable`.
+    fn unset_variable(&mut self, name: &str) {
+        remove_var(name);
+        if name == "PATH" {
+            self.command_cache.borrow_mut().clear();
+        }
+    }
+}
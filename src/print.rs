@@ -0,0 +1,609 @@
+use crate::parameters::{is_valid_name, Parameters};
+use crate::shell::Osh;
+use crate::werror;
+
+use std::io::Write;
+
+use console::style;
+
+pub trait Print {
+    fn echo(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn printf(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl Print for Osh {
+    /// Implementation of the `echo` builtin: `echo [-neE] [ARG...]`.
+    ///
+    /// - `-n`: do not output the trailing newline
+    /// - `-e`: interpret backslash escapes
+    /// - `-E`: do not interpret backslash escapes (default)
+    fn echo(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut newline = true;
+        let mut escapes = false;
+
+        // Options are only recognized if they are made of valid flags, so `echo -nope`
+        // prints `-nope`
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.peek() {
+            let flags = match arg.strip_prefix('-') {
+                Some(f) if !f.is_empty() && f.chars().all(|c| "neE".contains(c)) => f,
+                _ => break,
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            args.next();
+        }
+
+        let mut result = Vec::new();
+        for (i, arg) in args.enumerate() {
+            if i > 0 {
+                result.push(b' ');
+            }
+            if !escapes {
+                result.extend_from_slice(arg.as_bytes());
+                continue;
+            }
+
+            // `\c` stops any further output, including the trailing newline
+            let (unescaped, stop) = unescape(arg, false);
+            result.extend(unescaped);
+            if stop {
+                newline = false;
+                break;
+            }
+        }
+        if newline {
+            result.push(b'\n');
+        }
+
+        write_output(output, &result, "echo")
+    }
+
+    /// Implementation of the `printf` builtin: `printf [-v VAR] FORMAT [ARG...]`.
+    ///
+    /// The format is reused as long as arguments remain, like in other shells. With `-v`, the
+    /// result is stored in `VAR` instead of being printed.
+    fn printf(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let (variable, args) = match args {
+            [flag, name, rest @ ..] if flag == "-v" => (Some(name), rest),
+            [flag, rest @ ..] if flag == "--" => (None, rest),
+            _ => (None, args),
+        };
+        let (format, mut args) = match args.split_first() {
+            Some((format, args)) => (format, args),
+            None => {
+                werror!("printf: usage: printf [-v var] format [arguments]");
+                return 2;
+            }
+        };
+        if let Some(name) = variable {
+            if !is_valid_name(name) {
+                werror!("printf: '{}': not a valid identifier", name);
+                return 1;
+            }
+        }
+
+        let mut result = Vec::new();
+        let mut status = 0;
+        loop {
+            let before = args.len();
+            let stop = format_once(format, &mut args, &mut result, &mut status);
+            // Recycle the format while arguments remain, unless it does not consume any
+            if stop || args.is_empty() || args.len() == before {
+                break;
+            }
+        }
+
+        match variable {
+            Some(name) => {
+                self.set_variable(name, String::from_utf8_lossy(&result).to_string());
+                status
+            }
+            None => match write_output(output, &result, "printf") {
+                0 => status,
+                s => s,
+            },
+        }
+    }
+}
+
+fn write_output(output: &mut dyn Write, content: &[u8], builtin: &str) -> u32 {
+    if let Err(e) = output.write_all(content).and_then(|_| output.flush()) {
+        werror!("{}: write error: {}", builtin, e);
+        return 1;
+    }
+    0
+}
+
+/// Interpret backslash escapes found in `value`. In a `printf` format, octal values are
+/// written `\NNN`, elsewhere they are written `\0NNN`.
+/// The boolean returned is true when `\c` was found, meaning output must stop.
+fn unescape(value: &str, in_format: bool) -> (Vec<u8>, bool) {
+    let mut result = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            result.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some(e) => e,
+            None => {
+                result.push(b'\\');
+                break;
+            }
+        };
+        match escaped {
+            '\\' => result.push(b'\\'),
+            'a' => result.push(0x07),
+            'b' => result.push(0x08),
+            'c' => return (result, true),
+            'e' | 'E' => result.push(0x1b),
+            'f' => result.push(0x0c),
+            'n' => result.push(b'\n'),
+            'r' => result.push(b'\r'),
+            't' => result.push(b'\t'),
+            'v' => result.push(0x0b),
+            '"' if in_format => result.push(b'"'),
+            '\'' if in_format => result.push(b'\''),
+            '0'..='7' if in_format || escaped == '0' => {
+                // `\NNN` in a format, `\0NNN` elsewhere: up to 3 octal digits either way
+                let mut digits = String::new();
+                if in_format {
+                    digits.push(escaped);
+                }
+                while digits.len() < 3 {
+                    match chars.peek() {
+                        Some(&d) if d.is_digit(8) => {
+                            digits.push(d);
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                result.push(u32::from_str_radix(&digits, 8).unwrap_or(0) as u8);
+            }
+            'x' | 'u' | 'U' => {
+                let max = match escaped {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let mut digits = String::new();
+                while digits.len() < max {
+                    match chars.peek() {
+                        Some(&d) if d.is_ascii_hexdigit() => {
+                            digits.push(d);
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+
+                if digits.is_empty() {
+                    result.push(b'\\');
+                    result.extend_from_slice(escaped.to_string().as_bytes());
+                } else if escaped == 'x' {
+                    result.push(u8::from_str_radix(&digits, 16).unwrap_or(0));
+                } else {
+                    let c = u32::from_str_radix(&digits, 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .unwrap_or(std::char::REPLACEMENT_CHARACTER);
+                    let mut buffer = [0; 4];
+                    result.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+            other => {
+                result.push(b'\\');
+                let mut buffer = [0; 4];
+                result.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+
+    (result, false)
+}
+
+/// Conversion specification: `%[flags][width][.precision]conversion`
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad `value` according to the width and the justification flag.
+    fn pad(&self, value: &str, zero_allowed: bool) -> String {
+        let width = self.width.unwrap_or(0);
+        let len = value.chars().count();
+        if len >= width {
+            return value.to_string();
+        }
+
+        let padding = width - len;
+        if self.left {
+            format!("{}{}", value, " ".repeat(padding))
+        } else if self.zero && zero_allowed {
+            // Zeros go after the sign and the base prefix
+            let mut prefix_len = value.starts_with(&['+', '-', ' '][..]) as usize;
+            if value[prefix_len..].starts_with("0x") || value[prefix_len..].starts_with("0X") {
+                prefix_len += 2;
+            }
+            let (prefix, digits) = value.split_at(prefix_len);
+            format!("{}{}{}", prefix, "0".repeat(padding), digits)
+        } else {
+            format!("{}{}", " ".repeat(padding), value)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+/// Process `format` a single time, consuming arguments from `args`.
+/// Return true if `\c` or `%b` with `\c` stopped the output.
+fn format_once(format: &str, args: &mut &[String], result: &mut Vec<u8>, status: &mut u32) -> bool {
+    let mut next_arg = || -> Option<String> {
+        let (first, rest) = args.split_first()?;
+        *args = rest;
+        Some(first.clone())
+    };
+
+    let mut chars = format.chars().peekable();
+    let mut literal = String::new();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            literal.push('%');
+            continue;
+        }
+
+        // Flush literal text, which may contain escapes
+        let (unescaped, stop) = unescape(&literal, true);
+        result.extend(unescaped);
+        literal.clear();
+        if stop {
+            return true;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&f) = chars.peek() {
+            match f {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        let mut read_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let value = next_arg().map(|a| parse_integer(&a, status)).unwrap_or(0);
+                return Some(value);
+            }
+            let mut digits = String::new();
+            while let Some(&d) = chars.peek() {
+                if !d.is_ascii_digit() {
+                    break;
+                }
+                digits.push(d);
+                chars.next();
+            }
+            digits.parse::<i64>().ok()
+        };
+
+        if let Some(width) = read_number(&mut chars) {
+            if width < 0 {
+                spec.left = true;
+            }
+            spec.width = Some(width.unsigned_abs() as usize);
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(read_number(&mut chars).unwrap_or(0).max(0) as usize);
+        }
+
+        let conversion = match chars.next() {
+            Some(c) => c,
+            None => {
+                werror!("printf: '%': missing format character");
+                *status = 1;
+                return true;
+            }
+        };
+
+        let arg = next_arg();
+        let formatted = match conversion {
+            's' => {
+                let mut value = arg.unwrap_or_default();
+                if let Some(p) = spec.precision {
+                    value = value.chars().take(p).collect();
+                }
+                spec.pad(&value, false)
+            }
+            'b' => {
+                let (unescaped, stop) = unescape(&arg.unwrap_or_default(), false);
+                let mut value = String::from_utf8_lossy(&unescaped).to_string();
+                if let Some(p) = spec.precision {
+                    value = value.chars().take(p).collect();
+                }
+                result.extend_from_slice(spec.pad(&value, false).as_bytes());
+                if stop {
+                    return true;
+                }
+                continue;
+            }
+            'q' => spec.pad(&shell_words::quote(&arg.unwrap_or_default()), false),
+            'c' => {
+                let value = arg.and_then(|a| a.chars().next()).map(String::from);
+                spec.pad(&value.unwrap_or_default(), false)
+            }
+            'd' | 'i' => {
+                let value = arg.map(|a| parse_integer(&a, status)).unwrap_or(0);
+                let digits = with_precision(value.unsigned_abs().to_string(), spec.precision);
+                let number = format!("{}{}", spec.sign(value < 0), digits);
+                spec.pad(&number, spec.precision.is_none())
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                // Negative values are displayed in two's complement, like C does
+                let value = arg.map(|a| parse_integer(&a, status)).unwrap_or(0) as u64;
+                let digits = match conversion {
+                    'u' => value.to_string(),
+                    'x' => format!("{:x}", value),
+                    'X' => format!("{:X}", value),
+                    _ => format!("{:o}", value),
+                };
+                let mut digits = with_precision(digits, spec.precision);
+                if spec.alternate && value != 0 {
+                    match conversion {
+                        'x' => digits = format!("0x{}", digits),
+                        'X' => digits = format!("0X{}", digits),
+                        'o' if !digits.starts_with('0') => digits = format!("0{}", digits),
+                        _ => (),
+                    }
+                }
+                spec.pad(&digits, spec.precision.is_none())
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = arg.map(|a| parse_float(&a, status)).unwrap_or(0.0);
+                let number = format_float(value, conversion, &spec);
+                spec.pad(&number, value.is_finite())
+            }
+            other => {
+                werror!("printf: '{}': invalid format character", other);
+                *status = 1;
+                return true;
+            }
+        };
+        result.extend_from_slice(formatted.as_bytes());
+    }
+
+    let (unescaped, stop) = unescape(&literal, true);
+    result.extend(unescaped);
+    stop
+}
+
+/// Prefix `digits` with zeros so it is at least `precision` long.
+fn with_precision(digits: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(p) if digits.len() < p => format!("{}{}", "0".repeat(p - digits.len()), digits),
+        // A null precision with a zero value produces no digit at all
+        Some(0) if digits == "0" => String::new(),
+        _ => digits,
+    }
+}
+
+/// Parse a numeric argument. Hexadecimal (`0x`), octal (`0`) and character values (`'a`)
+/// are supported. Invalid values are reported and converted to 0.
+fn parse_integer(arg: &str, status: &mut u32) -> i64 {
+    let trimmed = arg.trim();
+    if let Some(c) = trimmed
+        .strip_prefix('\'')
+        .or_else(|| trimmed.strip_prefix('"'))
+    {
+        return c.chars().next().map(|c| c as i64).unwrap_or(0);
+    }
+
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<u64>()
+    };
+
+    match parsed {
+        Ok(v) if negative => (v as i64).wrapping_neg(),
+        Ok(v) => v as i64,
+        Err(_) => {
+            werror!("printf: '{}': invalid number", arg);
+            *status = 1;
+            0
+        }
+    }
+}
+
+fn parse_float(arg: &str, status: &mut u32) -> f64 {
+    let trimmed = arg.trim();
+    if trimmed.starts_with('\'') || trimmed.starts_with('"') {
+        return parse_integer(trimmed, status) as f64;
+    }
+    match trimmed.parse::<f64>() {
+        Ok(v) => v,
+        Err(_) => {
+            werror!("printf: '{}': invalid number", arg);
+            *status = 1;
+            0.0
+        }
+    }
+}
+
+/// Format a floating point value the way C `printf` does for `%f`, `%e` and `%g`.
+fn format_float(value: f64, conversion: char, spec: &Spec) -> String {
+    let sign = spec.sign(value.is_sign_negative() && value != 0.0);
+    let value = value.abs();
+    let upper = conversion.is_ascii_uppercase();
+
+    if !value.is_finite() {
+        let s = if value.is_nan() { "nan" } else { "inf" };
+        let s = if upper { s.to_uppercase() } else { s.into() };
+        return format!("{}{}", sign, s);
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    let formatted = match conversion.to_ascii_lowercase() {
+        'f' => format!("{:.*}", precision, value),
+        'e' => format_exponent(value, precision),
+        _ => {
+            // %g uses the shortest of %e and %f, and removes trailing zeros
+            let precision = precision.max(1);
+            // The exponent must be computed after rounding, as 9.9999999 becomes 1.00000e+01
+            let exponent = format!("{:.*e}", precision - 1, value)
+                .split_once('e')
+                .and_then(|(_, e)| e.parse::<i32>().ok())
+                .unwrap_or(0);
+            let formatted = if exponent < -4 || exponent >= precision as i32 {
+                format_exponent(value, precision - 1)
+            } else {
+                format!(
+                    "{:.*}",
+                    (precision as i32 - 1 - exponent).max(0) as usize,
+                    value
+                )
+            };
+            if spec.alternate {
+                formatted
+            } else {
+                strip_trailing_zeros(&formatted)
+            }
+        }
+    };
+
+    let formatted = if upper {
+        formatted.to_uppercase()
+    } else {
+        formatted
+    };
+    format!("{}{}", sign, formatted)
+}
+
+/// Rust writes exponents as `1.5e3` while C writes `1.5e+03`.
+fn format_exponent(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    match formatted.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let exponent = exponent.parse::<i32>().unwrap_or(0);
+            let sign = if exponent < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+        }
+        None => formatted,
+    }
+}
+
+fn strip_trailing_zeros(value: &str) -> String {
+    let (mantissa, exponent) = match value.find('e') {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_expanded() {
+        let cases: &[(&str, &[&str], &str, u32)] = &[
+            ("%s-%d\\n", &["a", "42"], "a-42\n", 0),
+            ("%5s|%-5s|", &["ab", "cd"], "   ab|cd   |", 0),
+            ("%05d|%+d|% d", &["42", "7", "7"], "00042|+7| 7", 0),
+            (
+                "%x %o %X %#x",
+                &["255", "8", "255", "255"],
+                "ff 10 FF 0xff",
+                0,
+            ),
+            ("%.2f|%8.3f", &["3.14159", "-2.5"], "3.14|  -2.500", 0),
+            (
+                "%e|%g|%g",
+                &["1234.5", "0.0001", "100000000"],
+                "1.234500e+03|0.0001|1e+08",
+                0,
+            ),
+            ("%c%c", &["hello", "é"], "hé", 0),
+            ("%d", &["'A"], "65", 0),
+            ("%.3s", &["abcdef"], "abc", 0),
+            ("%b", &["a\\tb\\0101"], "a\tbA", 0),
+            ("100%%", &[], "100%", 0),
+            ("%s %s|%d", &["a"], "a |0", 0),
+            ("%d", &["12abc"], "0", 1),
+        ];
+
+        for (format, args, expected, expected_status) in cases {
+            let args = args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+            let mut result = Vec::new();
+            let mut status = 0;
+            assert!(!format_once(
+                format,
+                &mut &args[..],
+                &mut result,
+                &mut status
+            ));
+            assert_eq!(String::from_utf8_lossy(&result), *expected, "{:?}", format);
+            assert_eq!(status, *expected_status, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn output_stops_at_backslash_c() {
+        let mut result = Vec::new();
+        let mut status = 0;
+        assert!(format_once("a\\cb", &mut &[][..], &mut result, &mut status));
+        assert_eq!(result, b"a");
+    }
+}
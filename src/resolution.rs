@@ -0,0 +1,339 @@
+use crate::alias::Alias;
+use crate::shell::{Osh, KEYWORDS};
+use crate::werror;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use console::style;
+
+/// What a command name resolves to, in order of precedence.
+#[derive(Debug, PartialEq)]
+pub enum Resolution {
+    Keyword,
+    Alias(String),
+    Builtin,
+    File(PathBuf),
+}
+
+/// Check that `path` is a regular file that can be executed.
+pub fn is_executable(path: &Path) -> bool {
+    match path.metadata() {
+        Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// List every executable named `name` found in the directories of `PATH`.
+pub fn find_in_path(name: &str) -> Vec<PathBuf> {
+    // Paths are used as is, without looking into PATH
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return if is_executable(&path) {
+            vec![path]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let path = env::var("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .filter(|candidate| is_executable(candidate))
+        .collect()
+}
+
+/// Cache of the paths external commands resolve to, so `PATH` is not searched every time a
+/// command is run. It must be cleared whenever `PATH` changes.
+#[derive(Default)]
+pub struct CommandCache {
+    /// Path of each command along with the number of times it has been run
+    entries: HashMap<String, (PathBuf, u32)>,
+    /// Names checked by `is_known` that were not found, so highlighting does not search `PATH`
+    /// on every keystroke
+    misses: HashSet<String>,
+}
+
+impl CommandCache {
+    /// Find the path of the command `name`, searching `PATH` if it is not cached yet.
+    pub fn find(&mut self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return find_in_path(name).into_iter().next();
+        }
+
+        // The executable may have been removed since it was cached
+        if let Some((path, _)) = self.entries.get(name) {
+            if is_executable(path) {
+                return Some(path.clone());
+            }
+            self.entries.remove(name);
+        }
+
+        let path = find_in_path(name).into_iter().next()?;
+        self.entries.insert(name.to_string(), (path.clone(), 0));
+        Some(path)
+    }
+
+    /// Whether `name` is an external command, remembering names that are not. Commands
+    /// installed since are only noticed once `forget_misses` or `clear` is called.
+    pub fn is_known(&mut self, name: &str) -> bool {
+        if self.misses.contains(name) {
+            return false;
+        }
+        let found = self.find(name).is_some();
+        if !found {
+            self.misses.insert(name.to_string());
+        }
+        found
+    }
+
+    pub fn forget_misses(&mut self) {
+        self.misses.clear();
+    }
+
+    /// Same as `find`, but the command is accounted for as being run.
+    pub fn lookup(&mut self, name: &str) -> Option<PathBuf> {
+        let path = self.find(name)?;
+        if let Some((_, hits)) = self.entries.get_mut(name) {
+            *hits += 1;
+        }
+        Some(path)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PathBuf> {
+        self.entries.get(name).map(|(path, _)| path)
+    }
+
+    pub fn insert(&mut self, name: &str, path: PathBuf) {
+        self.misses.remove(name);
+        self.entries.insert(name.to_string(), (path, 0));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.misses.clear();
+    }
+
+    /// List cached commands, sorted by name.
+    pub fn entries(&self) -> Vec<(&String, &PathBuf, u32)> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(name, (path, hits))| (name, path, *hits))
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+}
+
+pub trait Resolve {
+    fn resolve(&self, name: &str) -> Vec<Resolution>;
+    fn describe(&self, args: &[String], output: &mut dyn Write, builtin: &str) -> u32;
+    fn hash(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+}
+
+impl Resolve for Osh {
+    /// List everything `name` resolves to. The first entry is the one used when running it.
+    fn resolve(&self, name: &str) -> Vec<Resolution> {
+        let mut resolutions = Vec::new();
+
+        if KEYWORDS.contains(&name) {
+            resolutions.push(Resolution::Keyword);
+        }
+        if let Some(alias) = self.lookup_aliases(name) {
+            resolutions.push(Resolution::Alias(alias));
+        }
+        if self.builtins.contains(name) {
+            resolutions.push(Resolution::Builtin);
+        }
+        for path in find_in_path(name) {
+            resolutions.push(Resolution::File(path));
+        }
+
+        resolutions
+    }
+
+    /// Implementation of `type [-a] [-t] [-p] NAME...`, also used by `command -v` and
+    /// `command -V`.
+    ///
+    /// - `-a`: display every resolution instead of the first one
+    /// - `-t`: only display the kind of resolution: `keyword`, `alias`, `builtin` or `file`
+    /// - `-p`: only display paths, for names resolving to a file
+    /// - `-v`: (`command` only) display the name, or the path for files
+    fn describe(&self, args: &[String], output: &mut dyn Write, builtin: &str) -> u32 {
+        let mut all = false;
+        let mut kind_only = false;
+        let mut path_only = false;
+        let mut short = false;
+        let mut names = Vec::new();
+
+        for arg in args {
+            match arg.strip_prefix('-') {
+                Some(flags) if names.is_empty() && !flags.is_empty() => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'a' => all = true,
+                            't' => kind_only = true,
+                            'p' => path_only = true,
+                            'v' if builtin == "command" => short = true,
+                            'V' if builtin == "command" => (),
+                            _ => {
+                                werror!("{}: -{}: invalid option", builtin, flag);
+                                return 2;
+                            }
+                        }
+                    }
+                }
+                _ => names.push(arg),
+            }
+        }
+
+        let mut status = 0;
+        let mut lines = Vec::new();
+        for name in names {
+            let mut resolutions = self.resolve(name);
+            if resolutions.is_empty() {
+                // `command -v` stays silent, like other shells
+                if !short && !kind_only {
+                    werror!("{}: {}: not found", builtin, name);
+                }
+                status = 1;
+                continue;
+            }
+            if !all {
+                resolutions.truncate(1);
+            }
+
+            for resolution in resolutions {
+                let line = match (&resolution, kind_only, path_only, short) {
+                    (Resolution::Keyword, true, _, _) => "keyword".into(),
+                    (Resolution::Alias(_), true, _, _) => "alias".into(),
+                    (Resolution::Builtin, true, _, _) => "builtin".into(),
+                    (Resolution::File(_), true, _, _) => "file".into(),
+                    (Resolution::File(path), _, true, _) | (Resolution::File(path), _, _, true) => {
+                        path.display().to_string()
+                    }
+                    (_, _, true, _) => continue,
+                    (Resolution::Alias(alias), _, _, true) => {
+                        format!("alias {}={}", name, shell_words::quote(alias))
+                    }
+                    (Resolution::Keyword, _, _, true) | (Resolution::Builtin, _, _, true) => {
+                        name.to_string()
+                    }
+                    (Resolution::Alias(alias), _, _, _) => {
+                        format!("{} is aliased to `{}'", name, alias)
+                    }
+                    (Resolution::Keyword, _, _, _) => format!("{} is a shell keyword", name),
+                    (Resolution::Builtin, _, _, _) => format!("{} is a shell builtin", name),
+                    (Resolution::File(path), _, _, _) => {
+                        if self.command_cache.borrow().get(name) == Some(path) {
+                            format!("{} is hashed ({})", name, path.display())
+                        } else {
+                            format!("{} is {}", name, path.display())
+                        }
+                    }
+                };
+                lines.push(line);
+            }
+        }
+
+        for line in lines {
+            if let Err(e) = writeln!(output, "{}", line) {
+                werror!("{}: write error: {}", builtin, e);
+                return 1;
+            }
+        }
+        status
+    }
+
+    /// Implementation of the `hash` builtin, to manage the cache of command paths:
+    ///
+    /// - `hash`: display cached commands and the number of times they have been run
+    /// - `hash NAME...`: search `NAME` in `PATH` and cache the result
+    /// - `hash -r`: forget every cached path
+    /// - `hash -d NAME...`: forget the path of `NAME`
+    /// - `hash -l`: display cached commands in a format that can be reused as input
+    /// - `hash -p PATH NAME`: use `PATH` for `NAME` without searching `PATH`
+    /// - `hash -t NAME...`: display the cached path of `NAME`
+    fn hash(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut cache = self.command_cache.borrow_mut();
+        let mut status = 0;
+        let mut lines = Vec::new();
+
+        match args.split_first() {
+            None => {
+                if cache.entries().is_empty() {
+                    lines.push("hash: hash table empty".to_string());
+                } else {
+                    lines.push("hits\tcommand".to_string());
+                    for (_, path, hits) in cache.entries() {
+                        lines.push(format!("{:>4}\t{}", hits, path.display()));
+                    }
+                }
+            }
+            Some((flag, _)) if flag == "-r" => cache.clear(),
+            Some((flag, _)) if flag == "-l" => {
+                for (name, path, _) in cache.entries() {
+                    lines.push(format!("builtin hash -p {} {}", path.display(), name));
+                }
+            }
+            Some((flag, names)) if flag == "-d" => {
+                for name in names {
+                    if !cache.remove(name) {
+                        werror!("hash: {}: not found", name);
+                        status = 1;
+                    }
+                }
+            }
+            Some((flag, rest)) if flag == "-p" => match rest {
+                [path, name] => cache.insert(name, PathBuf::from(path)),
+                _ => {
+                    werror!("hash: usage: hash -p PATH NAME");
+                    status = 2;
+                }
+            },
+            Some((flag, names)) if flag == "-t" => {
+                for name in names {
+                    match cache.get(name) {
+                        Some(path) => lines.push(path.display().to_string()),
+                        None => {
+                            werror!("hash: {}: not found", name);
+                            status = 1;
+                        }
+                    }
+                }
+            }
+            Some((flag, _)) if flag.starts_with('-') => {
+                werror!("hash: {}: invalid option", flag);
+                status = 2;
+            }
+            Some(_) => {
+                for name in args {
+                    // Builtins never go through the cache
+                    if self.builtins.contains(name) {
+                        continue;
+                    }
+                    if cache.find(name).is_none() {
+                        werror!("hash: {}: not found", name);
+                        status = 1;
+                    }
+                }
+            }
+        }
+
+        for line in lines {
+            if let Err(e) = writeln!(output, "{}", line) {
+                werror!("hash: write error: {}", e);
+                return 1;
+            }
+        }
+        status
+    }
+}
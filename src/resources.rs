@@ -0,0 +1,424 @@
+use crate::shell::Osh;
+use crate::werror;
+
+use std::io::{self, Write};
+
+use console::style;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Resources handled by `ulimit`: option, resource, description, unit and the number of
+/// bytes of the unit, used to convert values from and to the unit of the kernel.
+const LIMITS: &[(char, Resource, &str, &str, u64)] = &[
+    ('c', libc::RLIMIT_CORE, "core file size", "blocks", 512),
+    ('d', libc::RLIMIT_DATA, "data seg size", "kbytes", 1024),
+    ('e', libc::RLIMIT_NICE, "scheduling priority", "", 1),
+    ('f', libc::RLIMIT_FSIZE, "file size", "blocks", 512),
+    ('i', libc::RLIMIT_SIGPENDING, "pending signals", "", 1),
+    (
+        'l',
+        libc::RLIMIT_MEMLOCK,
+        "max locked memory",
+        "kbytes",
+        1024,
+    ),
+    ('m', libc::RLIMIT_RSS, "max memory size", "kbytes", 1024),
+    ('n', libc::RLIMIT_NOFILE, "open files", "", 1),
+    (
+        'q',
+        libc::RLIMIT_MSGQUEUE,
+        "POSIX message queues",
+        "bytes",
+        1,
+    ),
+    ('r', libc::RLIMIT_RTPRIO, "real-time priority", "", 1),
+    ('s', libc::RLIMIT_STACK, "stack size", "kbytes", 1024),
+    ('t', libc::RLIMIT_CPU, "cpu time", "seconds", 1),
+    ('u', libc::RLIMIT_NPROC, "max user processes", "", 1),
+    ('v', libc::RLIMIT_AS, "virtual memory", "kbytes", 1024),
+    ('x', libc::RLIMIT_LOCKS, "file locks", "", 1),
+];
+
+fn get_limit(resource: Resource) -> io::Result<libc::rlimit> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit)
+}
+
+fn format_limit(value: libc::rlim_t, unit: u64) -> String {
+    if value == libc::RLIM_INFINITY {
+        "unlimited".into()
+    } else {
+        (value / unit as libc::rlim_t).to_string()
+    }
+}
+
+/// Format `seconds` like `1m2.345s`, as `times` does.
+fn format_duration(time: libc::timeval) -> String {
+    let seconds = time.tv_sec as f64 + time.tv_usec as f64 / 1_000_000.0;
+    format!("{}m{:.3}s", (seconds / 60.0) as u64, seconds % 60.0)
+}
+
+/// Apply the symbolic mode `mode`, such as `u=rwx,g+r,o-w`, to the permissions `permissions`.
+fn apply_symbolic_mode(mode: &str, mut permissions: u32) -> Option<u32> {
+    for clause in mode.split(',') {
+        let who_len = clause
+            .find(|c| !matches!(c, 'u' | 'g' | 'o' | 'a'))
+            .unwrap_or(clause.len());
+        let (who, mut actions) = clause.split_at(who_len);
+        let mut who_mask = who.chars().fold(0, |mask, c| match c {
+            'u' => mask | 0o700,
+            'g' => mask | 0o070,
+            'o' => mask | 0o007,
+            _ => mask | 0o777,
+        });
+        if who_mask == 0 {
+            who_mask = 0o777;
+        }
+
+        // Several actions can follow each other, such as `u+r-w`
+        if actions.is_empty() {
+            return None;
+        }
+        while let Some(operator) = actions.chars().next() {
+            if !matches!(operator, '+' | '-' | '=') {
+                return None;
+            }
+            let perms_len = actions[1..]
+                .find(['+', '-', '='])
+                .map_or(actions.len(), |i| i + 1);
+            let mut bits = 0;
+            for perm in actions[1..perms_len].chars() {
+                bits |= match perm {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    _ => return None,
+                };
+            }
+            bits &= who_mask;
+
+            match operator {
+                '+' => permissions |= bits,
+                '-' => permissions &= !bits,
+                _ => permissions = (permissions & !who_mask) | bits,
+            }
+            actions = &actions[perms_len..];
+        }
+    }
+    Some(permissions)
+}
+
+/// Describe the permissions granted by `mask` like `u=rwx,g=rx,o=rx`.
+fn symbolic_mask(mask: u32) -> String {
+    let permissions = !mask & 0o777;
+    ["u", "g", "o"]
+        .iter()
+        .enumerate()
+        .map(|(i, who)| {
+            let bits = (permissions >> (6 - 3 * i)) & 0o7;
+            let perms = [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')]
+                .iter()
+                .filter(|(bit, _)| bits & bit != 0)
+                .map(|(_, c)| *c)
+                .collect::<String>();
+            format!("{}={}", who, perms)
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+pub trait Resources {
+    fn ulimit(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn umask(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn times(&self, output: &mut dyn Write) -> u32;
+}
+
+impl Resources for Osh {
+    /// Implementation of `ulimit [-HS] [-a | -cdefilmnqrstuvx [LIMIT]]`, to display or set the
+    /// limits of the resources available to the shell and the commands it runs:
+    ///
+    /// - `-a`: display every limit
+    /// - `-H`: use the hard limit
+    /// - `-S`: use the soft limit. Both limits are set when neither `-H` nor `-S` is supplied,
+    ///   the soft one is displayed
+    /// - `LIMIT`: a number in the unit of the resource, `unlimited`, `hard` or `soft`
+    ///
+    /// The file size limit (`-f`) is used when no resource is supplied.
+    fn ulimit(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut hard = false;
+        let mut soft = false;
+        let mut all = false;
+        let mut resources = Vec::new();
+        let mut value = None;
+
+        for arg in args {
+            match arg.strip_prefix('-') {
+                Some(flags) if !flags.is_empty() && value.is_none() => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'H' => hard = true,
+                            'S' => soft = true,
+                            'a' => all = true,
+                            _ => match LIMITS.iter().find(|l| l.0 == flag) {
+                                Some(limit) => resources.push(limit),
+                                None => {
+                                    werror!("ulimit: -{}: invalid option", flag);
+                                    return 2;
+                                }
+                            },
+                        }
+                    }
+                }
+                _ if value.is_none() => value = Some(arg),
+                _ => {
+                    werror!("ulimit: {}: too many arguments", arg);
+                    return 2;
+                }
+            }
+        }
+
+        if all {
+            resources = LIMITS.iter().collect();
+        } else if resources.is_empty() {
+            resources.extend(LIMITS.iter().find(|l| l.0 == 'f'));
+        }
+
+        let value = match value {
+            Some(v) => v,
+            None => {
+                let mut lines = Vec::new();
+                for (flag, resource, description, unit, size) in resources.iter() {
+                    let limit = match get_limit(*resource) {
+                        Ok(l) => l,
+                        Err(e) => {
+                            werror!("ulimit: {}: cannot get limit: {}", description, e);
+                            return 1;
+                        }
+                    };
+                    let value =
+                        format_limit(if hard { limit.rlim_max } else { limit.rlim_cur }, *size);
+
+                    // Descriptions are only useful when several limits are displayed
+                    if resources.len() > 1 {
+                        let option = if unit.is_empty() {
+                            format!("(-{})", flag)
+                        } else {
+                            format!("({}, -{})", unit, flag)
+                        };
+                        lines.push(format!("{:<24}{:>16} {}", description, option, value));
+                    } else {
+                        lines.push(value);
+                    }
+                }
+
+                for line in lines {
+                    if let Err(e) = writeln!(output, "{}", line) {
+                        werror!("ulimit: write error: {}", e);
+                        return 1;
+                    }
+                }
+                return 0;
+            }
+        };
+
+        if all {
+            werror!("ulimit: {}: cannot set every limit at once", value);
+            return 2;
+        }
+
+        let mut status = 0;
+        for (_, resource, description, _, size) in resources {
+            let mut limit = match get_limit(*resource) {
+                Ok(l) => l,
+                Err(e) => {
+                    werror!("ulimit: {}: cannot get limit: {}", description, e);
+                    status = 1;
+                    continue;
+                }
+            };
+
+            let new_value = match value.as_str() {
+                "unlimited" => libc::RLIM_INFINITY,
+                "hard" => limit.rlim_max,
+                "soft" => limit.rlim_cur,
+                _ => match value.parse::<u64>() {
+                    Ok(v) => match v.checked_mul(*size) {
+                        Some(v) => v as libc::rlim_t,
+                        None => libc::RLIM_INFINITY,
+                    },
+                    Err(_) => {
+                        werror!("ulimit: {}: invalid number", value);
+                        return 1;
+                    }
+                },
+            };
+
+            if hard || !soft {
+                limit.rlim_max = new_value;
+            }
+            if soft || !hard {
+                limit.rlim_cur = new_value;
+            }
+
+            if unsafe { libc::setrlimit(*resource, &limit) } != 0 {
+                werror!(
+                    "ulimit: {}: cannot modify limit: {}",
+                    description,
+                    io::Error::last_os_error()
+                );
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// Implementation of `umask [-p] [-S] [MODE]`, to display or set the file mode creation
+    /// mask:
+    ///
+    /// - `MODE`: an octal mask such as `077`, or the permissions to grant such as `u=rwx,go=`
+    /// - `-S`: display the permissions granted by the mask instead of the mask itself
+    /// - `-p`: display the mask in a format that can be reused as input
+    fn umask(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let mut symbolic = false;
+        let mut reusable = false;
+        let mut mode = None;
+
+        for arg in args {
+            match arg.as_str() {
+                "-S" => symbolic = true,
+                "-p" => reusable = true,
+                _ if arg.starts_with('-')
+                    && arg.len() > 1
+                    && arg[1..].chars().all(|c| c.is_alphabetic()) =>
+                {
+                    werror!("umask: {}: invalid option", arg);
+                    return 2;
+                }
+                _ if mode.is_none() => mode = Some(arg),
+                _ => {
+                    werror!("umask: {}: too many arguments", arg);
+                    return 2;
+                }
+            }
+        }
+
+        // Reading the mask requires setting it, so restore it right away
+        let mask = unsafe {
+            let mask = libc::umask(0);
+            libc::umask(mask);
+            mask as u32
+        };
+
+        let mode = match mode {
+            Some(m) => m,
+            None => {
+                let line = match (symbolic, reusable) {
+                    (true, true) => format!("umask -S {}", symbolic_mask(mask)),
+                    (true, false) => symbolic_mask(mask),
+                    (false, true) => format!("umask {:04o}", mask),
+                    (false, false) => format!("{:04o}", mask),
+                };
+                if let Err(e) = writeln!(output, "{}", line) {
+                    werror!("umask: write error: {}", e);
+                    return 1;
+                }
+                return 0;
+            }
+        };
+
+        let new_mask = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+            match u32::from_str_radix(mode, 8) {
+                Ok(m) if m <= 0o777 => m,
+                _ => {
+                    werror!("umask: {}: octal number out of range", mode);
+                    return 1;
+                }
+            }
+        } else {
+            match apply_symbolic_mode(mode, !mask & 0o777) {
+                Some(permissions) => !permissions & 0o777,
+                None => {
+                    werror!("umask: {}: invalid symbolic mode", mode);
+                    return 1;
+                }
+            }
+        };
+
+        unsafe {
+            libc::umask(new_mask as libc::mode_t);
+        }
+        0
+    }
+
+    /// Implementation of the `times` builtin: display the user and system times accumulated by
+    /// the shell on the first line, by the commands it ran on the second line.
+    fn times(&self, output: &mut dyn Write) -> u32 {
+        let mut lines = Vec::new();
+        for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN].iter() {
+            let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+            if unsafe { libc::getrusage(*who, &mut usage) } != 0 {
+                werror!("times: {}", io::Error::last_os_error());
+                return 1;
+            }
+            lines.push(format!(
+                "{} {}",
+                format_duration(usage.ru_utime),
+                format_duration(usage.ru_stime)
+            ));
+        }
+
+        for line in lines {
+            if let Err(e) = writeln!(output, "{}", line) {
+                werror!("times: write error: {}", e);
+                return 1;
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbolic_modes_are_applied() {
+        let cases: &[(&str, u32, Option<u32>)] = &[
+            ("u=rwx,g=rx,o=", 0o000, Some(0o750)),
+            ("g+w", 0o755, Some(0o775)),
+            ("a-x", 0o777, Some(0o666)),
+            ("+x", 0o644, Some(0o755)),
+            ("u+r-w", 0o600, Some(0o400)),
+            ("go=", 0o755, Some(0o700)),
+            ("ug=r,o+w", 0o000, Some(0o442)),
+            ("u+z", 0o755, None),
+            ("u", 0o755, None),
+            ("k+r", 0o755, None),
+            ("", 0o755, None),
+        ];
+
+        for (mode, permissions, expected) in cases {
+            assert_eq!(
+                apply_symbolic_mode(mode, *permissions),
+                *expected,
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn masks_are_described() {
+        assert_eq!(symbolic_mask(0o022), "u=rwx,g=rx,o=rx");
+        assert_eq!(symbolic_mask(0o077), "u=rwx,g=,o=");
+    }
+}
@@ -1,8 +1,13 @@
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::env::current_dir;
 use std::fs;
 use std::io::Cursor;
-use std::path::{self, Path};
+use std::path::{self, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use rustyline::completion::{escape, extract_word, unescape, Completer, Pair, Quote};
 use rustyline::error::ReadlineError;
@@ -12,6 +17,10 @@ use rustyline::validate::{self, MatchingBracketValidator, Validator};
 use rustyline::Context;
 use rustyline_derive::Helper;
 
+use crate::abbreviation::{find_abbreviation, Abbreviations};
+use crate::directory_stack::tilde_path;
+use crate::resolution::{is_executable, CommandCache};
+
 extern crate skim;
 use skim::prelude::*;
 
@@ -22,6 +31,104 @@ pub struct MyHelper {
     pub validator: MatchingBracketValidator,
     pub hinter: HistoryHinter,
     pub colored_prompt: String,
+    pub command_cache: Rc<RefCell<CommandCache>>,
+    /// Builtins and aliases, which are valid commands even though they are not in PATH
+    pub command_names: HashSet<String>,
+    /// Extensions of the files run by suffix aliases, such as `md`
+    pub suffixes: HashSet<String>,
+    /// Current directory followed by the directory stack, for `cd -N` completion
+    pub directories: Vec<PathBuf>,
+    /// Directories are valid commands when the `autocd` option is set
+    pub autocd: bool,
+    /// Abbreviations, expanded in place of completion when a space is typed after them
+    pub abbreviations: Abbreviations,
+    /// Set by `AbbreviationHandler` to have the next completion expand an abbreviation
+    pub abbreviation_requested: Arc<AtomicBool>,
+}
+
+impl MyHelper {
+    /// Check if `word` can be run as a command.
+    fn is_known_command(&self, word: &str) -> bool {
+        // Words that still need to be expanded cannot be checked
+        if word.contains(&['$', '~', '"', '\'', '*'][..]) {
+            return true;
+        }
+        if self.autocd && Path::new(word).is_dir() {
+            return true;
+        }
+        if word
+            .rsplit_once('.')
+            .is_some_and(|(_, extension)| self.suffixes.contains(extension))
+        {
+            return true;
+        }
+        if word.contains('/') {
+            return is_executable(Path::new(word));
+        }
+        self.command_names.contains(word) || self.command_cache.borrow_mut().is_known(word)
+    }
+
+    /// Complete `cd -N`, `cd +N`, `pushd` and `popd` indexes with the directories of the stack.
+    fn complete_directory_stack(&self, line: &str, pos: usize) -> Option<(usize, Vec<Pair>)> {
+        let (start, word) = extract_word(line, pos, None, b" ");
+        let command = line[..start].split_whitespace().last()?;
+        if !["cd", "pushd", "popd", "dirs"].contains(&command) {
+            return None;
+        }
+
+        let sign = word.chars().next().filter(|c| *c == '-' || *c == '+')?;
+        if !word[1..].chars().all(|c| c.is_ascii_digit()) || self.directories.len() < 2 {
+            return None;
+        }
+
+        // `+N` counts from the current directory, `-N` from the bottom of the stack
+        let len = self.directories.len();
+        let candidates = (0..len)
+            .map(|n| {
+                let directory = if sign == '+' {
+                    &self.directories[n]
+                } else {
+                    &self.directories[len - 1 - n]
+                };
+                (format!("{}{}", sign, n), directory)
+            })
+            .filter(|(index, _)| index.starts_with(word))
+            .map(|(index, directory)| Pair {
+                display: format!("{} -- {}", index, tilde_path(directory)),
+                replacement: index,
+            })
+            .collect();
+
+        Some((start, candidates))
+    }
+
+    /// Display commands that cannot be found in red.
+    fn highlight_unknown_commands<'l>(&self, line: &'l str) -> Cow<'l, str> {
+        let mut highlighted = String::new();
+        let mut found_unknown = false;
+        let mut command_position = true;
+        let mut last = 0;
+
+        let words = line
+            .split_whitespace()
+            .map(|w| (w.as_ptr() as usize - line.as_ptr() as usize, w));
+        for (start, word) in words {
+            if command_position && !self.is_known_command(word) {
+                highlighted += &line[last..start];
+                highlighted += &format!("\x1b[31m{}\x1b[m", word);
+                last = start + word.len();
+                found_unknown = true;
+            }
+            // The word following a separator is a command as well
+            command_position = matches!(word, "|" | ";" | "&&" | "||");
+        }
+
+        if !found_unknown {
+            return Borrowed(line);
+        }
+        highlighted += &line[last..];
+        Owned(highlighted)
+    }
 }
 
 impl Completer for MyHelper {
@@ -33,6 +140,21 @@ impl Completer for MyHelper {
         pos: usize,
         ctx: &Context<'_>,
     ) -> Result<(usize, Vec<Pair>), ReadlineError> {
+        if let Some(completion) = self.complete_directory_stack(line, pos) {
+            return Ok(completion);
+        }
+        let expansion = if self.abbreviation_requested.swap(false, Ordering::Relaxed) {
+            find_abbreviation(line, pos, &self.abbreviations.read().unwrap())
+        } else {
+            None
+        };
+        if let Some((start, expansion)) = expansion {
+            let candidate = Pair {
+                replacement: format!("{} ", expansion),
+                display: expansion,
+            };
+            return Ok((start, vec![candidate]));
+        }
         self.completer.complete(line, pos, ctx)
     }
 }
@@ -66,11 +188,16 @@ impl Highlighter for MyHelper {
     }
 
     fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
-        self.highlighter.highlight(line, pos)
+        // Matching brackets take precedence over unknown commands
+        match self.highlighter.highlight(line, pos) {
+            Borrowed(line) => self.highlight_unknown_commands(line),
+            highlighted => highlighted,
+        }
     }
 
-    fn highlight_char(&self, line: &str, pos: usize) -> bool {
-        self.highlighter.highlight_char(line, pos)
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        // Commands must be checked every time the line changes
+        true
     }
 }
 
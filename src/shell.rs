@@ -1,23 +1,47 @@
 // use crate::alias::list_aliases;
 // use crate::alias::lookup_aliases;
-use crate::alias::Alias;
+use crate::abbreviation::{
+    expand_abbreviations, AbbreviationHandler, Abbreviations, AcceptHandler,
+};
+use crate::alias::{Alias, AliasKind};
+use crate::builtins::{Builtin, Builtins, InputStream, Output, StreamGuard, Streams};
+use crate::change_directory::logical_current_dir;
+use crate::command_not_found::CommandNotFound;
 
 use crate::config::ConfigFile;
+use crate::config_watcher::{ConfigWatcher, WatchConfig};
+use crate::directory_config::{DirectoryConfig, DirectoryConfiguration};
+use crate::directory_stack::DirectoryStack;
+use crate::frecency::FrecencyDatabase;
+use crate::jobs::{Job, Jobs};
+use crate::parameters::Parameters;
+use crate::resolution::CommandCache;
 use crate::rustyline_helper::{MyFilenameCompleter, MyHelper};
+use crate::timing::{Timing, Usage};
+use crate::trap::Trap;
 use crate::utils::Utils;
 use crate::{wdebug, werror, winfo};
 
-use std::collections::HashMap;
-use std::env::{self, remove_var, set_var};
-use std::fs::OpenOptions;
-use std::io::{stdout, Write};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env::{self, set_var};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdout, Cursor, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
 
 use rustyline::error::ReadlineError;
+use rustyline::history::History;
 use rustyline::Movement;
 use rustyline::Word;
-use rustyline::{Cmd, Config, Editor, KeyEvent};
+use rustyline::{Cmd, Config, Editor, EventHandler, KeyCode, KeyEvent, Modifiers};
 
 use rustyline::config::OutputStreamType;
 use rustyline::highlight::MatchingBracketHighlighter;
@@ -31,36 +55,127 @@ use console::style;
 struct ShellCommand {
     command: String,
     args: Vec<String>,
-    redirection: Redirection,
+    redirections: Vec<Redirection>,
     piped: bool,
 }
 
-#[allow(dead_code)]
+/// Reserved words, handled by the shell before aliases are expanded
+pub const KEYWORDS: &[&str] = &["time"];
+
+/// Operators introducing a redirection
+const REDIRECTIONS: &[&str] = &["<", ">", ">>", "2>", "2>>", "&>", "&>>"];
+
+/// Redirection of a standard stream to a file. The boolean is set when appending to the file.
 #[derive(Debug, PartialEq)]
 enum Redirection {
-    None,
-    Stdout,
-    Stderr,
-    Both,
+    /// `< FILE`
+    Stdin(String),
+    /// `> FILE` or `>> FILE`
+    Stdout(String, bool),
+    /// `2> FILE` or `2>> FILE`
+    Stderr(String, bool),
+    /// `&> FILE` or `&>> FILE`
+    Both(String, bool),
+}
+
+/// Files the standard streams of a command are redirected to
+#[derive(Default)]
+struct RedirectedFiles {
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+}
+
+impl RedirectedFiles {
+    fn open(redirections: &[Redirection]) -> Result<RedirectedFiles, String> {
+        let open = |path: &str, append: bool| {
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(path)
+                .map_err(|e| format!("{}: {}", path, e))
+        };
+
+        // Later redirections of the same stream take precedence, like in other shells
+        let mut files = RedirectedFiles::default();
+        for redirection in redirections {
+            match redirection {
+                Redirection::Stdin(path) => {
+                    files.stdin = Some(File::open(path).map_err(|e| format!("{}: {}", path, e))?)
+                }
+                Redirection::Stdout(path, append) => files.stdout = Some(open(path, *append)?),
+                Redirection::Stderr(path, append) => files.stderr = Some(open(path, *append)?),
+                Redirection::Both(path, append) => {
+                    let file = open(path, *append)?;
+                    files.stderr = Some(file.try_clone().map_err(|e| format!("{}: {}", path, e))?);
+                    files.stdout = Some(file);
+                }
+            }
+        }
+        Ok(files)
+    }
 }
 
 pub struct Osh {
-    config: ConfigFile,
+    pub config: ConfigFile,
     pub aliases: HashMap<String, String>,
+    /// Aliases expanded anywhere in the command line: `alias -g`
+    pub global_aliases: HashMap<String, String>,
+    /// Programs used to run files according to their extension: `alias -s`
+    pub suffix_aliases: HashMap<String, String>,
+    /// Words expanded while typing, shared with the key handler expanding them
+    pub abbreviations: Abbreviations,
     rl: Editor<MyHelper>,
     history_path: String,
-    status: u32,
+    pub status: u32,
     prompt: String,
-    previous_directory: PathBuf,
+    /// Commands to run when a signal or pseudo-signal (EXIT, ERR) is received
+    pub traps: HashMap<String, String>,
+    /// Set while a trap is running to prevent traps from triggering themselves
+    pub in_trap: bool,
+    /// Positional parameters: `$1`, `$2`...
+    pub positional: Vec<String>,
+    /// Name of the shell or of the script being executed: `$0`
+    pub script_name: String,
+    /// PID of the last command run in background: `$!`
+    pub last_background_pid: Option<u32>,
+    /// Indexed arrays, such as the ones filled by `read -a`
+    pub arrays: HashMap<String, Vec<String>>,
+    /// Paths of the external commands already run, shared with the syntax highlighter
+    pub command_cache: Rc<RefCell<CommandCache>>,
+    /// Set while `command_not_found_handle` runs to prevent it from calling itself
+    pub in_not_found_handler: bool,
+    /// Directories saved by `pushd`, the most recent first
+    pub directory_stack: Vec<PathBuf>,
+    /// Directories visited with `cd`, used by `z`
+    pub frecency: FrecencyDatabase,
+    /// Commands run by the shell itself
+    pub builtins: Builtins,
+    /// Resources used by the commands waited for, reset by `time`
+    pub children_usage: Usage,
+    /// Commands run in background, the most recent last
+    pub jobs: Vec<Job>,
+    /// Configuration file given with `--config`, loaded after the other ones
+    pub config_path: Option<PathBuf>,
+    /// Notices changes of the configuration files in interactive sessions
+    pub config_watcher: Option<ConfigWatcher>,
+    /// Variables and aliases of the `.osh.yaml` and `.oshenv` files of the current directory
+    pub directory_config: DirectoryConfig,
+    /// Set when reading commands from the terminal rather than from a script: `i` in `$-`
+    pub interactive: bool,
+}
 
-struct BuiltinCommandResult {
-    is_builtin: bool,
-    /// Determine if we skip any further processing after builtin command execution
-    skip: bool,
+/// Output of a command of a pipeline, used as input of the next command
+enum PipeInput {
+    Child(ChildStdout),
+    /// Builtins are run by the shell itself, so their output is buffered
+    Builtin(Vec<u8>),
 }
 
 impl Osh {
-    pub fn new() -> Self {
+    pub fn new(config_path: Option<PathBuf>) -> Self {
         // Initialize interactive prompt
         let editor_config = Config::builder()
             .history_ignore_space(true)
@@ -70,12 +185,23 @@ impl Osh {
             .output_stream(OutputStreamType::Stdout)
             .build();
 
+        let command_cache = Rc::new(RefCell::new(CommandCache::default()));
+        let abbreviations = Arc::new(RwLock::new(HashMap::new()));
+        let abbreviation_requested = Arc::new(AtomicBool::new(false));
+
         let helper = MyHelper {
             completer: MyFilenameCompleter::new(),
             highlighter: MatchingBracketHighlighter::new(),
             hinter: HistoryHinter {},
             colored_prompt: ">>>".to_owned(),
             validator: MatchingBracketValidator::new(),
+            command_cache: command_cache.clone(),
+            command_names: HashSet::new(),
+            suffixes: HashSet::new(),
+            directories: Vec::new(),
+            autocd: false,
+            abbreviations: abbreviations.clone(),
+            abbreviation_requested: abbreviation_requested.clone(),
         };
 
         let mut rl = Editor::with_config(editor_config);
@@ -98,6 +224,22 @@ impl Osh {
             },
         );
 
+        // Expand abbreviations when a space is typed after them, or when the line is accepted
+        rl.bind_sequence(
+            KeyEvent(KeyCode::Char(' '), Modifiers::NONE),
+            EventHandler::Conditional(Box::new(AbbreviationHandler {
+                abbreviations: abbreviations.clone(),
+                requested: abbreviation_requested,
+            })),
+        );
+        rl.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(AcceptHandler {
+                abbreviations: abbreviations.clone(),
+                expanded: AtomicBool::new(false),
+            })),
+        );
+
         // Load history of previous sessions
         let homedir = match env::var("HOME") {
             Ok(val) => val,
@@ -107,24 +249,54 @@ impl Osh {
             // TODO: Avoid collision when used by multiple users (even without $USER)
             Err(_) => "/tmp".into(),
         };
-        let history_path = homedir + "/.history";
+        let history_path = homedir.clone() + "/.history";
+        let frecency = FrecencyDatabase::load(PathBuf::from(homedir + "/.frecency"));
         let _ = rl.load_history(&history_path);
 
         let status = 0u32;
 
-        let config = ConfigFile::new();
-        let aliases = config.aliases.clone();
+        // Make sure PWD designates the current directory, without resolving symlinks the user
+        // went through before starting the shell
+        set_var("PWD", logical_current_dir());
+
+        let config = ConfigFile::new(config_path.as_deref());
+        let aliases = config.aliases.command.clone();
+        let global_aliases = config.aliases.global.clone();
+        let suffix_aliases = config.aliases.suffix.clone();
+        abbreviations
+            .write()
+            .unwrap()
+            .extend(config.abbreviations.clone());
 
         let prompt = Osh::build_prompt(&config, status);
 
         Osh {
             config,
             aliases,
+            global_aliases,
+            suffix_aliases,
+            abbreviations,
             history_path,
             rl,
             status,
             prompt,
-            previous_directory: env::current_dir().unwrap(),
+            traps: HashMap::new(),
+            in_trap: false,
+            positional: Vec::new(),
+            script_name: env::args().next().unwrap_or_else(|| "osh".into()),
+            last_background_pid: None,
+            arrays: HashMap::new(),
+            command_cache,
+            in_not_found_handler: false,
+            directory_stack: Vec::new(),
+            frecency,
+            builtins: Builtins::new(),
+            children_usage: Usage::default(),
+            jobs: Vec::new(),
+            config_path,
+            config_watcher: None,
+            directory_config: DirectoryConfig::default(),
+            interactive: false,
         }
     }
 
@@ -133,16 +305,8 @@ impl Osh {
     fn build_prompt(config: &ConfigFile, status: u32) -> String {
         let mut prompt = String::new();
 
-        // Fetch current directory
-        let cwd = match env::current_dir() {
-            Ok(d) => d,
-            Err(e) => {
-                werror!("{}", e);
-                // Use empty value if current directory cannot be read from env
-                // It is very unlikely but who knows
-                std::path::PathBuf::new()
-            }
-        };
+        // Fetch current directory, as reached by the user
+        let cwd = logical_current_dir();
 
         wdebug!(config, "cwd: {:?}", cwd);
         wdebug!(config, "config.username: {:?}", config.username);
@@ -154,6 +318,12 @@ impl Osh {
             prompt += &style(format!("@{} ", &config.hostname)).green().to_string();
         }
 
+        // Variables such as `$CMD_DURATION` are expanded, a lone `$` is kept as is
+        let symbol = Osh::perform_variable_expansion(&config.prompt, &|name| {
+            env::var(name).into_iter().collect()
+        })
+        .join(" ");
+
         if status == 0 {
             prompt += &format!(
                 "{} {} ",
@@ -161,7 +331,7 @@ impl Osh {
                     .blue()
                     .bold()
                     .to_string(),
-                config.prompt
+                symbol
             );
         } else {
             prompt += &format!(
@@ -169,346 +339,535 @@ impl Osh {
                 style(cwd.to_str().unwrap().replace("\"", ""))
                     .bold()
                     .to_string(),
-                config.prompt
+                symbol
             );
         }
 
         prompt
     }
 
-    /// Check if the supplied command is a builtin and set flags accordingly
-    fn try_builtin(&mut self, shell_command: &ShellCommand) -> BuiltinCommandResult {
-        let mut result = BuiltinCommandResult {
-            is_builtin: true,
-            skip: false,
-        };
+    /// Use a configuration read again from the file. Aliases and abbreviations defined at
+    /// runtime are kept, the ones coming from the previous configuration are replaced.
+    pub fn apply_config(&mut self, config: ConfigFile) {
+        let mut previous = std::mem::replace(&mut self.config, config);
+        for kind in AliasKind::ALL.iter().copied() {
+            let previous = kind.config_aliases(&mut previous).clone();
+            let current = kind.config_aliases(&mut self.config).clone();
+            let aliases = self.alias_table(kind);
+            aliases.retain(|name, value| previous.get(name) != Some(value));
+            aliases.extend(current);
+        }
+        let mut abbreviations = self.abbreviations.write().unwrap();
+        abbreviations.retain(|name, value| previous.abbreviations.get(name) != Some(value));
+        abbreviations.extend(self.config.abbreviations.clone());
+    }
 
-        match &shell_command.command[..] {
-            "export" => {
-                let mut args = shell_command.args.iter();
-                let env_var = match args.next() {
-                    Some(v) => v.clone(),
-                    None => {
-                        werror!("No environment variable provided");
-                        self.status = 1;
-                        result.skip = true;
-                        return result;
-                    }
-                };
+    /// Lines entered in previous and current sessions, oldest first.
+    pub fn history(&self) -> &History {
+        self.rl.history()
+    }
 
-                match args.next() {
-                    Some(v) => {
-                        set_var(env_var, v);
-                    }
-                    None => {
-                        remove_var(env_var);
-                    }
-                };
+    pub fn save_history(&mut self) {
+        self.rl.save_history(&self.history_path).unwrap();
+    }
 
-                self.status = 0;
-            }
-            "unset" => {
-                let mut args = shell_command.args.iter();
-                match args.next() {
-                    Some(v) => {
-                        remove_var(v);
-                    }
-                    None => {
-                        werror!("No environment variable provided");
-                        self.status = 1;
-                        result.skip = true;
+    pub fn repl(&mut self) -> rustyline::Result<()> {
+        self.interactive = true;
+        self.watch_config();
+        loop {
+            // Signals may have been received while the previous command was running
+            self.run_pending_traps();
+            // The configuration files may have been edited from elsewhere
+            self.reload_changed_config();
+            // The files of the current directory may have been edited or trusted
+            self.update_directory_config();
+            // Let the user know about the jobs done in background
+            self.notify_jobs();
+
+            self.prompt = Osh::build_prompt(&self.config, self.status);
+            // Need to explicitly flush to ensure it prints before read_line
+            stdout().flush().unwrap();
+            self.rl.helper_mut().expect("No helper").colored_prompt = self.prompt.clone();
+            // Let the highlighter know about commands that are not in PATH
+            self.rl.helper_mut().expect("No helper").command_names = self
+                .builtins
+                .names()
+                .iter()
+                .chain(KEYWORDS.iter())
+                .map(|b| b.to_string())
+                .chain(self.aliases.keys().cloned())
+                .chain(self.global_aliases.keys().cloned())
+                .chain(self.abbreviations.read().unwrap().keys().cloned())
+                .collect();
+            self.rl.helper_mut().expect("No helper").suffixes =
+                self.suffix_aliases.keys().cloned().collect();
+            // Commands may have been installed while the previous one was running
+            self.command_cache.borrow_mut().forget_misses();
+            // Let the completer offer the directories of the stack
+            self.rl.helper_mut().expect("No helper").directories = self.directories();
+            self.rl.helper_mut().expect("No helper").autocd = self.config.autocd;
+
+            match self.rl.readline(&self.prompt) {
+                Ok(line) => {
+                    if line.is_empty() {
+                        continue;
                     }
-                };
+                    let line = expand_abbreviations(&line, &self.abbreviations);
+
+                    // Save input in history
+                    self.rl.add_history_entry(line.as_str());
+                    self.rl.save_history(&self.history_path).unwrap();
+
+                    let start = Instant::now();
+                    self.run_line(&line);
+                    self.report_duration(&line, start.elapsed());
+                }
+                // CTRL + c at the prompt does not raise SIGINT since the terminal is in raw mode,
+                // but users expect their INT trap to be honored anyway
+                Err(ReadlineError::Interrupted) => self.run_trap("INT"),
+                Err(ReadlineError::Eof) => {
+                    self.run_exit_trap();
+                    return Ok(());
+                }
+                Err(err) => {
+                    werror!("Interactive error: {:?}. Exiting", err);
+                    break;
+                }
             }
-            "alias" => {
-                // Register a new alias
-                let mut args = shell_command.args.iter();
-                let new_alias = match args.next() {
-                    Some(v) => v.clone(),
-                    None => {
-                        self.list_aliases();
-                        self.status = 0;
-                        result.skip = true;
-                        return result;
-                    }
-                };
+        }
+        self.run_exit_trap();
+        self.rl.save_history(&self.history_path).unwrap();
 
-                // Build the command by parsing the rest of the command provided
-                let aliased = args.cloned().collect::<Vec<String>>().join(" ");
+        Ok(())
+    }
 
-                self.config.aliases.insert(new_alias, aliased);
-                self.status = 0;
+    /// Execute every line of the script located at `path` with `args` as positional
+    /// parameters and return the status of the last command executed.
+    pub fn run_script(&mut self, path: &str, args: &[String]) -> u32 {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                werror!("{}: '{}'", e, path);
+                return 127;
             }
-            "unalias" => {
-                result.is_builtin = true;
-                // Fetch the name of the new alias or display available aliases if not alias
-                // has been found
-                let mut args = shell_command.args.iter();
-                let request = match args.next() {
-                    Some(v) => v,
-                    None => {
-                        werror!("No alias provided");
-                        self.status = 1;
-                        result.skip = true;
-                        return result;
-                    }
-                };
+        };
 
-                if !self.config.aliases.contains_key(request) {
-                    werror!("{} is not an alias", request);
-                    self.status = 1;
-                    result.skip = true;
-                }
-                self.config.aliases.remove(request);
-                self.status = 0;
+        self.script_name = path.to_string();
+        self.positional = args.to_vec();
+
+        for line in content.lines() {
+            let line = line.trim();
+            // Skip empty lines, comments and shebang
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            "config" => {
-                let editor = match env::var("EDITOR") {
-                    Ok(e) => e,
-                    Err(_) => {
-                        werror!("EDITOR variable not set. Cannot open configuration file");
-                        self.status = 1;
-                        result.is_builtin = true;
-                        result.skip = true;
-                        return result;
-                    }
-                };
+            self.run_line(line);
+            self.run_pending_traps();
+        }
 
-                let _ = Command::new(editor)
-                    .args(vec![Osh::perform_expansion_on_single_element(
-                        "~/.shell.yaml",
-                    )])
-                    .stdin(Stdio::inherit())
-                    .stdout(Stdio::inherit())
-                    .spawn()
-                    .unwrap()
-                    .wait();
+        self.run_exit_trap();
+        self.status
+    }
 
-                self.config = ConfigFile::new();
-                winfo!("Configuration file reloaded");
+    /// Parse and execute a single command line.
+    pub fn run_line(&mut self, line: &str) {
+        let words = match shell_words::split(line) {
+            Ok(words) => words,
+            Err(e) => {
+                // Such as unbalanced quotes
+                werror!("syntax error: {}", e);
+                self.status = 2;
+                return;
+            }
+        };
 
-                self.status = 0;
+        // Commands separated by `;` or `&` are expanded right before being executed, so
+        // parameters such as `$?` reflect the result of the previous commands
+        for sequence in words.split_inclusive(|w| w == ";" || w == "&") {
+            let (background, sequence) = match sequence.split_last() {
+                Some((last, rest)) if last == "&" => (true, rest),
+                Some((last, rest)) if last == ";" => (false, rest),
+                _ => (false, sequence),
+            };
+            if sequence.is_empty() {
+                continue;
             }
-            "reload" => {
-                self.config = ConfigFile::new();
-                winfo!("Configuration file reloaded");
-                self.status = 0;
+            let result = if background {
+                self.execute_sequence(sequence, true)
+            } else {
+                self.run_sequence(sequence)
+            };
+            if !result {
+                return;
             }
-            "status" => {
-                winfo!("Status: {}", self.status);
-                self.status = 0;
+        }
+    }
+
+    /// Expand and execute a list of words that does not contain any `;` separator.
+    /// Return false if the remaining commands of the line must not be executed.
+    pub fn run_sequence(&mut self, words: &[String]) -> bool {
+        // `time` applies to the whole sequence, before aliases are expanded
+        if words[0] == "time" {
+            return self.time(&words[1..]);
+        }
+        self.execute_sequence(words, false)
+    }
+
+    /// Execute a sequence, without waiting for its external commands when it is run in
+    /// `background`: they are registered as a job instead. Builtins are part of the shell, so
+    /// they always run in foreground.
+    fn execute_sequence(&mut self, words: &[String], background: bool) -> bool {
+        // Arguments of these commands are stored to be used later on, so they must be kept as is
+        let is_verbatim_command = words[0].eq("unalias") || words[0].eq("trap");
+        let mut previous_command = None;
+        // Commands of the current pipeline, waited for once its last command is done
+        let mut pipeline: Vec<Child> = Vec::new();
+
+        // For each command, use an alias if available. It allows user to use aliases
+        // even in the commands following | character
+        let mut resolved = Vec::new();
+        // Set when the previous word was an alias ending with a blank
+        let mut trailing_blank = false;
+        // Number of words used as arguments of a parameterised alias
+        let mut consumed = 0;
+        // Position of the first word of the current command of the pipeline
+        let mut first_word = 0;
+        for (i, command) in words.iter().enumerate() {
+            if consumed > 0 {
+                consumed -= 1;
+                continue;
             }
-            "history" => {
-                result.is_builtin = true;
 
-                for (i, h) in self.rl.history().iter().enumerate() {
-                    println!("{:>3} :: {}", i, h);
-                }
-                self.status = 0;
+            if is_verbatim_command {
+                // We're dealing with "unalias" or "trap" command so we need to make sure to keep
+                // value as is
+                resolved.push(command.to_string());
+                continue;
             }
-            "cd" => {
-                // default to '~' of '/' as new directory if one was not provided
-                let dir = match env::var("HOME") {
-                    Ok(val) => val,
-                    Err(_) => {
-                        werror!("HOME variable not set. Using / as default target");
-                        "/".into()
-                    }
-                };
-                let mut args = shell_command.args.iter();
-                let new_dir = match args.next() {
-                    Some(v) => v,
-                    None => &dir[..],
-                };
 
-                let target;
-                // Use "-" to go to the last directory visited
-                if new_dir == "-" {
-                    target = self.previous_directory.to_str().unwrap();
+            // Other aliases than global ones only apply to the first word of a command
+            let command_position = i == 0 || words[i - 1] == "|" || trailing_blank;
+            trailing_blank = false;
+            if i == 0 || words[i - 1] == "|" {
+                first_word = i;
+            }
+            // Arguments of these commands are command or alias names, which must not be replaced
+            // by their alias
+            let bypass_alias = i > first_word
+                && ["alias", "builtin", "command", "type"].contains(&words[first_word].as_str());
+
+            for (j, expanded) in self.expand_parameters(command).into_iter().enumerate() {
+                // If we've found an alias, resolve it and parse the resolved string as a new
+                // command, since it can be composed of several words
+                let alias = if bypass_alias {
+                    Ok(None)
+                } else if command_position && j == 0 {
+                    // The placeholders of aliases are filled with the words of the command
+                    let args = words[i + 1..]
+                        .iter()
+                        .take_while(|w| *w != "|" && !REDIRECTIONS.contains(&w.as_str()))
+                        .map(|w| self.expand_parameters(w).join(" "))
+                        .collect::<Vec<String>>();
+                    self.expand_alias_recursively(&expanded, true, &args)
                 } else {
-                    target = new_dir;
-                }
-
-                // Perform variable expansion
-                let target = Osh::perform_expansion_on_single_element(target);
-                // Save the location we're in before changing directory
-                let dir_before_cd = env::current_dir().unwrap();
-
-                if let Err(e) = env::set_current_dir(Path::new(&target)) {
-                    werror!("Error: {}: '{}'", e, target);
+                    self.expand_alias_recursively(&expanded, false, &[])
+                };
+                if let Err(e) = &alias {
+                    werror!("{}", e);
                     self.status = 1;
-                    result.skip = true;
+                    self.run_err_trap();
+                    return false;
                 }
+                if let Ok(Some(expansion)) = alias {
+                    resolved.extend(expansion.words);
+                    trailing_blank = expansion.trailing_blank;
+                    consumed = expansion.consumed;
+                } else if let Some(wildcard_expanded) = Osh::perform_wildcard_expansion(&expanded) {
+                    for w in wildcard_expanded.iter() {
+                        resolved.push(w.to_string());
+                    }
+                } else {
+                    // If no alias has been found, no wildcard expanded, simply use the
+                    // word as is
+                    resolved.push(expanded);
+                }
+            }
+        }
 
-                // Update the last directory if need be
-                if env::current_dir().unwrap() != dir_before_cd {
-                    self.previous_directory =
-                        Path::new(dir_before_cd.to_str().unwrap()).to_path_buf();
+        // Now the command line has been preprocessed, split it in several commands to
+        // execute
+        let shell_commands = match self.build_commands(resolved) {
+            Ok(c) => c,
+            Err(e) => {
+                werror!("{}", e);
+                self.status = 2;
+                self.run_err_trap();
+                return false;
+            }
+        };
+        for mut shell_command in shell_commands {
+            // `command NAME` and `builtin NAME` simply run `NAME`, since its alias has been
+            // bypassed already
+            let mut force_builtin = false;
+            while shell_command.command == "command" || shell_command.command == "builtin" {
+                match shell_command.args.first() {
+                    Some(arg) if !arg.starts_with('-') => {
+                        force_builtin |= shell_command.command == "builtin";
+                        shell_command.command = shell_command.args.remove(0);
+                    }
+                    _ => break,
                 }
+            }
 
-                self.status = 0;
+            // With `autocd`, directories can be used as commands to go there
+            if self.config.autocd
+                && shell_command.args.is_empty()
+                && !self.builtins.contains(&shell_command.command)
+                && Path::new(&shell_command.command).is_dir()
+                && self
+                    .command_cache
+                    .borrow_mut()
+                    .find(&shell_command.command)
+                    .is_none()
+            {
+                let dir = std::mem::replace(&mut shell_command.command, "cd".into());
+                shell_command.args.push(dir);
             }
-            _ => {
-                result.is_builtin = false;
+
+            let builtin = self.builtins.get(&shell_command.command);
+            if force_builtin && builtin.is_none() {
+                werror!("builtin: {}: not a shell builtin", shell_command.command);
+                self.status = 1;
+                self.run_err_trap();
+                previous_command = None;
+                continue;
             }
-        }
-        result
-    }
 
-    pub fn repl(&mut self) -> rustyline::Result<()> {
-        'shell_loop: loop {
-            self.prompt = Osh::build_prompt(&self.config, self.status);
-            // Need to explicitly flush to ensure it prints before read_line
-            stdout().flush().unwrap();
-            self.rl.helper_mut().expect("No helper").colored_prompt = self.prompt.clone();
+            let files = match RedirectedFiles::open(&shell_command.redirections) {
+                Ok(f) => f,
+                Err(e) => {
+                    werror!("{}", e);
+                    self.status = 1;
+                    self.run_err_trap();
+                    previous_command = None;
+                    continue;
+                }
+            };
 
-            match self.rl.readline(&self.prompt) {
-                Ok(line) => {
-                    if line.is_empty() {
-                        continue;
-                    }
+            if let Some(builtin) = builtin {
+                previous_command =
+                    self.run_builtin(builtin.as_ref(), &shell_command, files, previous_command);
+            } else {
+                let command = shell_command.command;
+                let (stdin, pending_input) = match (files.stdin, previous_command) {
+                    (Some(file), _) => (Stdio::from(file), None),
+                    (None, Some(PipeInput::Child(output))) => (Stdio::from(output), None),
+                    (None, Some(PipeInput::Builtin(output))) => (Stdio::piped(), Some(output)),
+                    // Jobs in background must not compete with the shell for the terminal
+                    (None, None) if background => (Stdio::null(), None),
+                    (None, None) => (Stdio::inherit(), None),
+                };
 
-                    // Save input in history
-                    self.rl.add_history_entry(line.as_str());
-                    self.rl.save_history(&self.history_path).unwrap();
+                let stdout = match files.stdout {
+                    Some(file) => Stdio::from(file),
+                    None if shell_command.piped => Stdio::piped(),
+                    None => Stdio::inherit(),
+                };
+                let stderr = match files.stderr {
+                    Some(file) => Stdio::from(file),
+                    None => Stdio::inherit(),
+                };
 
-                    let commands = shell_words::split(&line).expect("Failed to split command line");
-                    let is_unalias_command = commands[0].eq("unalias");
-                    let mut previous_command = None;
-
-                    // For each command, use an alias if available. It allows user to use aliases
-                    // even in the commands following | character
-                    let mut resolved = Vec::new();
-                    for command in commands {
-                        let expanded = Osh::perform_expansion_on_single_element(&command);
-
-                        if !is_unalias_command {
-                            // If we've found an alias, resolve it and parse the resolved string as a new
-                            // command, since it can be composed of several words
-                            if let Some(resolved_alias) = self.lookup_aliases(&expanded) {
-                                let parts = shell_words::split(&resolved_alias)
-                                    .expect("Failed to split resolved alias");
-                                for part in parts {
-                                    resolved.push(part);
-                                }
-                            } else if let Some(wildcard_expanded) =
-                                Osh::perform_wildcard_expansion(&expanded)
-                            {
-                                for w in wildcard_expanded.iter() {
-                                    resolved.push(w.to_string());
-                                }
-                            } else {
-                                // If no alias has been found, no wildcard expanded, simply use the
-                                // word as is
-                                resolved.push(expanded);
-                            }
-                        } else {
-                            // We're dealing with "unalias" command so we need to make sure to keep
-                            // value as is
-                            resolved.push(expanded);
+                wdebug!(self.config, "Command            : {}", command);
+                wdebug!(
+                    self.config,
+                    "Command args       : {:#?}",
+                    &shell_command.args
+                );
+                wdebug!(self.config, "Command piped      : {}", &shell_command.piped);
+                wdebug!(
+                    self.config,
+                    "Command redirection: {:#?}",
+                    &shell_command.redirections
+                );
+
+                // Resolve the command through the cache instead of letting `Command` search
+                // PATH every time
+                let path = self.command_cache.borrow_mut().lookup(&command);
+                let child = match &path {
+                    Some(path) => {
+                        let mut process = Command::new(path);
+                        process
+                            .arg0(&command)
+                            .args(&shell_command.args)
+                            .stdin(stdin)
+                            .stdout(stdout)
+                            .stderr(stderr);
+                        // Jobs in background get a process group of their own, led by their
+                        // first process, so the signals of the terminal such as Ctrl-C do not
+                        // reach them
+                        if background {
+                            let leader = pipeline.first().map_or(0, |p| p.id());
+                            process.process_group(leader as i32);
                         }
+                        process.spawn()
                     }
+                    None => Err(io::Error::new(io::ErrorKind::NotFound, "command not found")),
+                };
+
+                match child {
+                    Ok(mut child) => {
+                        self.status = 0;
 
-                    // Now the command line has been preprocessed, split it in several commands to
-                    // execute
-                    let shell_commands = self.build_commands(resolved);
-                    for shell_command in shell_commands {
-                        // Try to execute the command as builtin if need be
-                        let builtin_result = self.try_builtin(&shell_command);
-                        if builtin_result.skip {
-                            continue 'shell_loop;
+                        // Write from another thread so a command which does not read its input
+                        // cannot block the shell
+                        if let Some(input) = pending_input {
+                            let mut child_stdin = child.stdin.take().unwrap();
+                            thread::spawn(move || child_stdin.write_all(&input));
                         }
 
-                        if !builtin_result.is_builtin {
-                            let command = shell_command.command;
-                            let stdin = previous_command
-                                .map_or(Stdio::inherit(), |output: Child| {
-                                    Stdio::from(output.stdout.unwrap())
-                                });
-
-                            let mut stdout = Stdio::inherit();
-                            let mut stderr = Stdio::inherit();
-                            if shell_command.piped {
-                                stdout = Stdio::piped();
-                                stderr = Stdio::piped();
-                            } else if shell_command.redirection != Redirection::None {
-                                stdout = Stdio::piped();
-                                stderr = Stdio::piped();
-                            }
-
-                            wdebug!(self.config, "Command            : {}", command);
-                            wdebug!(
-                                self.config,
-                                "Command args       : {:#?}",
-                                &shell_command.args
-                            );
-                            wdebug!(self.config, "Command piped      : {}", &shell_command.piped);
-                            wdebug!(
-                                self.config,
-                                "Command redirection: {:#?}",
-                                &shell_command.redirection
-                            );
-
-                            let child = Command::new(command.clone())
-                                .args(shell_command.args)
-                                .stdin(stdin)
-                                .stdout(stdout)
-                                .stderr(stderr)
-                                .spawn();
-
-                            match child {
-                                Ok(child) => {
-                                    self.status = 0;
-
-                                    if !shell_command.piped {
-                                        child.wait_with_output().expect("failed to wait on child");
-                                        previous_command = None;
-                                    } else {
-                                        previous_command = Some(child);
-                                    }
-
-                                    // self.child = None;
-
-                                    // // Process redirection if need be
-                                    // if shell_command.redirection != Redirection::None {
-                                    //     let _o = &child
-                                    //         .wait_with_output()
-                                    //         .expect("failed to wait on child");
-                                    //
-                                    //     wwarning!("TODO: manage redirection");
-                                    //     // redirect(&line, o);
-                                    //     previous_command = None;
-                                    //     previous_command = Some(child);
-                                    // } else {
-                                    //     println!("Previous command = {:#?}", &child);
-                                    //     previous_command = Some(child);
-                                    // }
-                                }
-                                Err(e) => {
-                                    previous_command = None;
-                                    werror!("{}: {:?}", e, command);
-                                    self.status = 1;
-                                }
-                            };
+                        if background && !shell_command.piped {
+                            pipeline.push(child);
+                            previous_command = None;
+                        } else if !shell_command.piped {
+                            let status = self.wait_child(&mut child);
+                            self.status = Osh::exit_code(status);
+                            previous_command = None;
+                        } else {
+                            // The previous command may have its output redirected to a file
+                            previous_command = Some(match child.stdout.take() {
+                                Some(output) => PipeInput::Child(output),
+                                None => PipeInput::Builtin(Vec::new()),
+                            });
+                            pipeline.push(child);
                         }
+
+                        // self.child = None;
+
+                        // // Process redirection if need be
+                        // if shell_command.redirection != Redirection::None {
+                        //     let _o = &child
+                        //         .wait_with_output()
+                        //         .expect("failed to wait on child");
+                        //
+                        //     wwarning!("TODO: manage redirection");
+                        //     // redirect(&line, o);
+                        //     previous_command = None;
+                        //     previous_command = Some(child);
+                        // } else {
+                        //     println!("Previous command = {:#?}", &child);
+                        //     previous_command = Some(child);
+                        // }
                     }
+                    Err(_) if path.is_none() => {
+                        previous_command = None;
+                        self.status = self.command_not_found(&command, &shell_command.args);
+                    }
+                    Err(e) => {
+                        previous_command = None;
+                        werror!("{}: {}", command, e);
+                        // Use the same statuses as other shells
+                        self.status = match e.kind() {
+                            io::ErrorKind::NotFound => 127,
+                            _ => 126,
+                        };
+                    }
+                };
+            }
+
+            if !shell_command.piped && !background {
+                for mut child in pipeline.drain(..) {
+                    self.wait_child(&mut child);
                 }
-                Err(ReadlineError::Interrupted) => (),
-                Err(ReadlineError::Eof) => {
-                    return Ok(());
-                }
-                Err(err) => {
-                    werror!("Interactive error: {:?}. Exiting", err);
-                    break;
+            }
+
+            if self.status != 0 {
+                self.run_err_trap();
+            }
+            self.run_pending_traps();
+        }
+
+        if background {
+            if !pipeline.is_empty() {
+                let pid = pipeline.last().map(|p| p.id()).unwrap_or_default();
+                let id = self.add_job(words.join(" "), pipeline);
+                if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+                    winfo!("[{}] {}", id, pid);
                 }
             }
+            return true;
         }
-        self.rl.save_history(&self.history_path).unwrap();
 
-        Ok(())
+        for mut child in pipeline {
+            self.wait_child(&mut child);
+        }
+
+        true
     }
 
-    fn build_commands(&self, words: Vec<String>) -> Vec<ShellCommand> {
+    /// Run a builtin with its standard streams connected to the previous and next commands of
+    /// the pipeline, or redirected to files. Return the input of the next command.
+    fn run_builtin(
+        &mut self,
+        builtin: &dyn Builtin,
+        shell_command: &ShellCommand,
+        mut files: RedirectedFiles,
+        input: Option<PipeInput>,
+    ) -> Option<PipeInput> {
+        let mut stdin = match (files.stdin.take(), input) {
+            (Some(file), _) => InputStream::File(file),
+            (None, Some(PipeInput::Child(output))) => InputStream::Pipe(output),
+            (None, Some(PipeInput::Builtin(output))) => InputStream::Buffer(Cursor::new(output)),
+            (None, None) => InputStream::Inherited,
+        };
+
+        // Errors reported by the builtin follow the redirection of its standard error
+        let _stderr_guard = files
+            .stderr
+            .as_ref()
+            .and_then(|f| StreamGuard::redirect(libc::STDERR_FILENO, f.as_raw_fd()));
+
+        // The output is kept aside when it must be fed to the next command of the pipeline
+        let destination = match files.stdout.as_ref() {
+            Some(file) => Output::File(file.as_raw_fd()),
+            None if shell_command.piped => Output::Piped,
+            None => Output::Inherited,
+        };
+        let mut buffer = Vec::new();
+        let mut shell_stdout = stdout();
+        let output: &mut dyn Write = match files.stdout.as_mut() {
+            Some(file) => file,
+            None if shell_command.piped => &mut buffer,
+            None => &mut shell_stdout,
+        };
+
+        let mut streams = Streams {
+            stdin: &mut stdin,
+            stdout: output,
+            stderr: &mut io::stderr(),
+            output: destination,
+        };
+        self.status = builtin.run(self, &shell_command.args, &mut streams);
+        let _ = streams.stdout.flush();
+
+        if shell_command.piped {
+            Some(PipeInput::Builtin(buffer))
+        } else {
+            None
+        }
+    }
+
+    /// Convert the exit status of a child into a shell status. Processes killed by a signal
+    /// are reported as 128 + signal number, like other shells do.
+    pub fn exit_code(status: ExitStatus) -> u32 {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => code as u32,
+            (None, Some(signal)) => 128 + signal as u32,
+            (None, None) => 1,
+        }
+    }
+
+    fn build_commands(&self, words: Vec<String>) -> Result<Vec<ShellCommand>, String> {
         let mut commands = Vec::new();
 
         // Find command separators
@@ -529,75 +888,42 @@ impl Osh {
             parts.push((current, false));
         }
 
-        for part in parts {
-            if part.0.len() > 1 {
-                let command = &part.0[0];
-                let piped = &part.1;
-                let redirection = Redirection::None;
-                let args = &part.0[1..];
-
-                // TODO: Parse args to find potential redirection
-                //
-                // In order to perform this operation:
-                // $ id > /tmp/asdf | grep uid
-                //
-                // This is not the kind of operation I'd like to do everyday but keeping a note is the
-                // best way to think about it some day
-
-                commands.push(ShellCommand {
-                    command: command.to_string(),
-                    args: args.to_vec(),
-                    redirection,
-                    piped: *piped,
-                })
-            } else {
-                // Command only contains the command itself, no redirection, no pipe
-                commands.push(ShellCommand {
-                    command: part.0[0].to_string(),
-                    args: Vec::new(),
-                    redirection: Redirection::None,
-                    piped: part.1,
-                });
+        for (part, piped) in parts {
+            // Redirections are followed by the name of a file and can be anywhere in the command
+            let mut words = part.into_iter();
+            let mut command_words = Vec::new();
+            let mut redirections = Vec::new();
+            while let Some(word) = words.next() {
+                let redirection: fn(String) -> Redirection = match word.as_str() {
+                    "<" => Redirection::Stdin,
+                    ">" => |path| Redirection::Stdout(path, false),
+                    ">>" => |path| Redirection::Stdout(path, true),
+                    "2>" => |path| Redirection::Stderr(path, false),
+                    "2>>" => |path| Redirection::Stderr(path, true),
+                    "&>" => |path| Redirection::Both(path, false),
+                    "&>>" => |path| Redirection::Both(path, true),
+                    _ => {
+                        command_words.push(word);
+                        continue;
+                    }
+                };
+                match words.next() {
+                    Some(path) => redirections.push(redirection(path)),
+                    None => return Err(format!("{}: missing file name", word)),
+                }
             }
-        }
-
-        commands
-    }
-
-    #[allow(dead_code)]
-    /// Redirect stdout and/or stderr to a file
-    fn redirect(command: &str, output: &std::process::Output) {
-        let filename;
-        let mut file_options = OpenOptions::new();
-
-        // Set default permissions
-        file_options.write(true);
-
-        let mut content = String::from_utf8_lossy(&output.stdout);
 
-        let mut parts = command.split('>');
-
-        // Determine if stderr needs to be redirected as well
-        if parts.next().unwrap().ends_with('2') {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            content += stderr;
-        }
-
-        // Determine if the file must be truncated or if the content should be
-        // appended
-        if command.contains(">>") {
-            file_options.append(true);
-            filename = command.split(">>").nth(1).unwrap().trim();
-        } else {
-            file_options.truncate(true);
-            file_options.create(true);
-            filename = command.split('>').nth(1).unwrap().trim();
+            if command_words.is_empty() {
+                return Err("syntax error: missing command".into());
+            }
+            commands.push(ShellCommand {
+                command: command_words.remove(0),
+                args: command_words,
+                redirections,
+                piped,
+            });
         }
 
-        let mut file = file_options
-            .open(filename)
-            .unwrap_or_else(|_| panic!("Failed to open {} to redirect content to it", filename));
-        write!(file, "{}", content)
-            .unwrap_or_else(|_| panic!("Failed to write content to redirect to {}", filename));
+        Ok(commands)
     }
 }
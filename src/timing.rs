@@ -0,0 +1,309 @@
+use crate::config::Notification;
+use crate::shell::Osh;
+use crate::{werror, winfo};
+
+use std::env::{self, set_var};
+use std::io::{self, stderr, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::time::{Duration, Instant};
+
+use console::style;
+
+/// Format used by `time` when `TIMEFORMAT` is not set
+const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS\nmaxrss\t%MK";
+
+/// Format used by `time -p`, as required by POSIX
+const POSIX_TIMEFORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+
+/// Resources used by processes, as reported by `wait4` and `getrusage`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub user: Duration,
+    pub system: Duration,
+    /// Peak resident set size, in kilobytes
+    pub max_rss: u64,
+}
+
+impl Usage {
+    fn from_rusage(usage: &libc::rusage) -> Usage {
+        let duration = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        };
+        Usage {
+            user: duration(usage.ru_utime),
+            system: duration(usage.ru_stime),
+            max_rss: usage.ru_maxrss as u64,
+        }
+    }
+
+    /// Resources used by the shell itself, which runs builtins.
+    fn current() -> Usage {
+        let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+        unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+        Usage::from_rusage(&usage)
+    }
+
+    /// Account for the resources of another process. The peak RSS is the highest of both.
+    pub fn add(&mut self, other: &Usage) {
+        self.user += other.user;
+        self.system += other.system;
+        self.max_rss = self.max_rss.max(other.max_rss);
+    }
+}
+
+/// Format `duration` with `precision` decimals, as `1m2.345s` if `long` is set.
+fn format_seconds(duration: Duration, precision: usize, long: bool) -> String {
+    let seconds = duration.as_secs_f64();
+    if long {
+        format!(
+            "{}m{:.*}s",
+            duration.as_secs() / 60,
+            precision,
+            seconds - (duration.as_secs() / 60 * 60) as f64
+        )
+    } else {
+        format!("{:.*}", precision, seconds)
+    }
+}
+
+/// Expand the sequences of `TIMEFORMAT` like bash does:
+///
+/// - `%[p][l]R`, `%[p][l]U`, `%[p][l]S`: elapsed real, user and system time, where `p` is the
+///   number of decimals (3 at most) and `l` displays minutes
+/// - `%P`: CPU percentage, computed as (user + system) / real
+/// - `%M`: peak resident set size in kilobytes
+/// - `%%`: a literal `%`
+fn format_times(format: &str, real: Duration, usage: &Usage) -> String {
+    let mut result = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        let mut precision = 3;
+        if let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            precision = digit.min(3) as usize;
+            chars.next();
+        }
+        let long = chars.peek() == Some(&'l');
+        if long {
+            chars.next();
+        }
+
+        match chars.next() {
+            Some('R') => result += &format_seconds(real, precision, long),
+            Some('U') => result += &format_seconds(usage.user, precision, long),
+            Some('S') => result += &format_seconds(usage.system, precision, long),
+            Some('P') => {
+                let cpu = (usage.user + usage.system).as_secs_f64();
+                let percentage = if real.as_secs_f64() > 0.0 {
+                    cpu * 100.0 / real.as_secs_f64()
+                } else {
+                    0.0
+                };
+                result += &format!("{:.*}", precision.min(2), percentage);
+            }
+            Some('M') => result += &usage.max_rss.to_string(),
+            Some('%') => result.push('%'),
+            Some(other) => {
+                result.push('%');
+                result.push(other);
+            }
+            None => result.push('%'),
+        }
+    }
+
+    result
+}
+
+/// How long to wait for the terminal to answer `terminal_focused`
+const FOCUS_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Whether the terminal has the focus, or `None` if it cannot tell.
+///
+/// Focus reports (`CSI ?1004h`) are enabled, then the primary device attributes are requested
+/// (`CSI c`), which every terminal answers. Terminals supporting focus reports send `CSI I` or
+/// `CSI O` as soon as they are enabled, before this answer. Reports are only enabled for the
+/// time of the check, so they do not reach the commands reading the terminal.
+fn terminal_focused() -> Option<bool> {
+    let tty = libc::STDIN_FILENO;
+    if unsafe { libc::isatty(tty) == 0 || libc::isatty(libc::STDERR_FILENO) == 0 } {
+        return None;
+    }
+
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(tty, &mut termios) } != 0 {
+        return None;
+    }
+    let mut raw = termios;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 0;
+    unsafe { libc::tcsetattr(tty, libc::TCSANOW, &raw) };
+
+    // Input typed while the command ran belongs to the line editor, and shows the user is there
+    let mut pending: libc::c_int = 0;
+    unsafe { libc::ioctl(tty, libc::FIONREAD, &mut pending) };
+    if pending > 0 {
+        unsafe { libc::tcsetattr(tty, libc::TCSANOW, &termios) };
+        return Some(true);
+    }
+
+    eprint!("\x1b[?1004h\x1b[c");
+    let _ = stderr().flush();
+
+    let mut focused = None;
+    let mut answer = Vec::new();
+    let start = Instant::now();
+    while let Some(remaining) = FOCUS_TIMEOUT.checked_sub(start.elapsed()) {
+        let mut poll = libc::pollfd {
+            fd: tty,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll, 1, remaining.as_millis() as libc::c_int) } <= 0 {
+            break;
+        }
+        let mut byte = 0u8;
+        if unsafe { libc::read(tty, &mut byte as *mut u8 as *mut libc::c_void, 1) } != 1 {
+            break;
+        }
+        answer.push(byte);
+        if answer.ends_with(b"\x1b[I") {
+            focused = Some(true);
+        } else if answer.ends_with(b"\x1b[O") {
+            focused = Some(false);
+        } else if byte == b'c' {
+            // The device attributes, such as `CSI ?62;22c`, come last
+            break;
+        }
+    }
+
+    eprint!("\x1b[?1004l");
+    let _ = stderr().flush();
+    unsafe { libc::tcsetattr(tty, libc::TCSANOW, &termios) };
+    focused
+}
+
+/// Format `duration` for humans, such as `1h 2m 3s` or `4.5s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}h {}m {}s",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        ),
+    }
+}
+
+pub trait Timing {
+    fn wait_child(&mut self, child: &mut Child) -> ExitStatus;
+    fn time(&mut self, words: &[String]) -> bool;
+    fn report_duration(&mut self, line: &str, elapsed: Duration);
+}
+
+impl Timing for Osh {
+    /// Wait for `child` to exit and account for the resources it used, so `time` can report
+    /// them.
+    fn wait_child(&mut self, child: &mut Child) -> ExitStatus {
+        // Close the input of the child, like `Child::wait` does, so it does not wait for more
+        // data
+        drop(child.stdin.take());
+
+        let mut status = 0;
+        let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+        loop {
+            let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+            if pid >= 0 {
+                break;
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                werror!("Failed to wait for process {}: {}", child.id(), error);
+                return ExitStatus::from_raw(1 << 8);
+            }
+        }
+
+        self.children_usage.add(&Usage::from_rusage(&usage));
+        ExitStatus::from_raw(status)
+    }
+
+    /// Implementation of the `time [-p] COMMAND` keyword: run the rest of the sequence, including
+    /// pipelines, and report the time it took on the standard error as `TIMEFORMAT` describes.
+    /// The user and system times of builtins, run by the shell itself, are included.
+    fn time(&mut self, words: &[String]) -> bool {
+        let (posix, words) = match words.split_first() {
+            Some((flag, rest)) if flag == "-p" => (true, rest),
+            _ => (false, words),
+        };
+
+        // `time` may be nested, the resources of the inner commands count for both
+        let outer_usage = std::mem::take(&mut self.children_usage);
+        let shell_usage = Usage::current();
+        let start = Instant::now();
+
+        let result = words.is_empty() || self.run_sequence(words);
+
+        let real = start.elapsed();
+        let children_usage = self.children_usage;
+        let mut usage = children_usage;
+        let current = Usage::current();
+        usage.user += current.user.saturating_sub(shell_usage.user);
+        usage.system += current.system.saturating_sub(shell_usage.system);
+        self.children_usage = outer_usage;
+        self.children_usage.add(&children_usage);
+
+        // An empty TIMEFORMAT disables the report
+        let format = if posix {
+            POSIX_TIMEFORMAT.to_string()
+        } else {
+            env::var("TIMEFORMAT").unwrap_or_else(|_| DEFAULT_TIMEFORMAT.into())
+        };
+        if !format.is_empty() {
+            eprintln!("{}", format_times(&format, real, &usage));
+        }
+
+        result
+    }
+
+    /// Store how long the command line `line` took in `CMD_DURATION`, in milliseconds, and
+    /// report it when it exceeds `report_time_threshold`. The terminal is notified only when
+    /// it does not have the focus, or when it cannot tell.
+    fn report_duration(&mut self, line: &str, elapsed: Duration) {
+        set_var("CMD_DURATION", elapsed.as_millis().to_string());
+
+        match self.config.report_time_threshold {
+            Some(threshold) if elapsed >= threshold => (),
+            _ => return,
+        }
+
+        let duration = format_duration(elapsed);
+        winfo!("Command took {}", duration);
+
+        let notification = self.config.report_time_notification;
+        if notification == Notification::None || terminal_focused() == Some(true) {
+            return;
+        }
+        match notification {
+            Notification::None => (),
+            Notification::Bell => eprint!("\x07"),
+            Notification::Osc777 => {
+                // The separator of the fields cannot be part of them
+                eprint!(
+                    "\x1b]777;notify;{};Finished in {}\x1b\\",
+                    line.replace(';', ","),
+                    duration
+                );
+            }
+        }
+        let _ = stderr().flush();
+    }
+}
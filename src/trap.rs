@@ -0,0 +1,236 @@
+use crate::shell::Osh;
+use crate::werror;
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use console::style;
+
+/// Signals that can be referred to by name, such as `INT`, `SIGINT`, or by number.
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Pseudo-signals handled by the shell itself rather than by the kernel.
+const PSEUDO_SIGNALS: &[&str] = &["EXIT", "ERR"];
+
+/// Bitmask of the signals received since the last time traps were run.
+/// Signal handlers can only do very little safely, so the actual work is deferred.
+static PENDING_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn record_signal(signal: libc::c_int) {
+    PENDING_SIGNALS.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Find the signal number associated to `name`. `INT`, `SIGINT`, `int` and `2` are all valid.
+pub fn signal_number(name: &str) -> Option<i32> {
+    if let Ok(n) = name.parse::<i32>() {
+        return SIGNALS.iter().find(|(_, s)| *s == n).map(|(_, s)| *s);
+    }
+    let name = name.to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(s, _)| *s == name).map(|(_, s)| *s)
+}
+
+/// Find the name associated to the signal number `signal`, without the `SIG` prefix.
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|(_, s)| *s == signal).map(|(s, _)| *s)
+}
+
+/// Normalize the condition supplied to `trap` so it can be used as a key of the trap table.
+fn trap_condition(name: &str) -> Option<String> {
+    if name == "0" || name.eq_ignore_ascii_case("EXIT") {
+        return Some("EXIT".into());
+    }
+    if name.eq_ignore_ascii_case("ERR") {
+        return Some("ERR".into());
+    }
+    signal_number(name).and_then(signal_name).map(|s| s.into())
+}
+
+pub trait Trap {
+    fn trap(&mut self, args: &[String], output: &mut dyn Write) -> u32;
+    fn list_traps(&self, conditions: &[String], output: &mut dyn Write) -> u32;
+    fn run_trap(&mut self, condition: &str);
+    fn run_pending_traps(&mut self);
+    fn run_err_trap(&mut self);
+    fn run_exit_trap(&mut self);
+}
+
+impl Trap for Osh {
+    /// Implementation of the `trap` builtin:
+    ///
+    /// - `trap` or `trap -p [CONDITION...]`: print registered traps
+    /// - `trap -l`: list signal names
+    /// - `trap - CONDITION...`: restore default behavior
+    /// - `trap '' CONDITION...`: ignore the signals
+    /// - `trap COMMAND CONDITION...`: run `COMMAND` when a condition is met
+    fn trap(&mut self, args: &[String], output: &mut dyn Write) -> u32 {
+        let (action, conditions) = match args.split_first() {
+            None => return self.list_traps(&[], output),
+            Some((first, rest)) if first == "-p" => return self.list_traps(rest, output),
+            Some((first, _)) if first == "-l" => {
+                for (name, number) in SIGNALS.iter() {
+                    if let Err(e) = writeln!(output, "{:>2}) SIG{}", number, name) {
+                        werror!("trap: write error: {}", e);
+                        return 1;
+                    }
+                }
+                return 0;
+            }
+            Some((first, rest)) if first == "--" => match rest.split_first() {
+                Some((action, conditions)) => (action, conditions),
+                None => return self.list_traps(&[], output),
+            },
+            Some((action, conditions)) => (action, conditions),
+        };
+
+        if conditions.is_empty() {
+            werror!("trap: no condition provided for '{}'", action);
+            return 1;
+        }
+
+        let mut status = 0;
+        for condition in conditions {
+            let condition = match trap_condition(condition) {
+                Some(c) => c,
+                None => {
+                    werror!("trap: {}: invalid signal specification", condition);
+                    status = 1;
+                    continue;
+                }
+            };
+
+            let signal = signal_number(&condition);
+            if signal == Some(libc::SIGKILL) || signal == Some(libc::SIGSTOP) {
+                werror!("trap: SIG{} cannot be trapped", condition);
+                status = 1;
+                continue;
+            }
+
+            let handler = if action == "-" {
+                self.traps.remove(&condition);
+                libc::SIG_DFL
+            } else if action.is_empty() {
+                self.traps.insert(condition, String::new());
+                libc::SIG_IGN
+            } else {
+                self.traps.insert(condition, action.to_string());
+                record_signal as extern "C" fn(libc::c_int) as libc::sighandler_t
+            };
+
+            if let Some(signal) = signal {
+                // SAFETY: `record_signal` only performs an atomic operation, which is
+                // async-signal-safe
+                unsafe {
+                    libc::signal(signal, handler);
+                }
+            }
+        }
+
+        status
+    }
+
+    /// Print registered traps in a format that can be reused as input.
+    fn list_traps(&self, conditions: &[String], output: &mut dyn Write) -> u32 {
+        let mut names: Vec<String> = if conditions.is_empty() {
+            self.traps.keys().cloned().collect()
+        } else {
+            conditions
+                .iter()
+                .filter_map(|c| trap_condition(c))
+                .collect()
+        };
+        names.sort_by_key(|n| signal_number(n).unwrap_or(-1));
+
+        for name in names {
+            if let Some(command) = self.traps.get(&name) {
+                let result = writeln!(
+                    output,
+                    "trap -- {} {}",
+                    shell_words::quote(command),
+                    if PSEUDO_SIGNALS.contains(&name.as_str()) {
+                        name
+                    } else {
+                        format!("SIG{}", name)
+                    }
+                );
+                if let Err(e) = result {
+                    werror!("trap: write error: {}", e);
+                    return 1;
+                }
+            }
+        }
+        0
+    }
+
+    /// Run the command registered for `condition` if any. The status of the last command is
+    /// preserved so `$?` is not altered by the trap itself.
+    fn run_trap(&mut self, condition: &str) {
+        if self.in_trap {
+            return;
+        }
+        let command = match self.traps.get(condition) {
+            Some(c) if !c.is_empty() => c.clone(),
+            _ => return,
+        };
+
+        let status = self.status;
+        self.in_trap = true;
+        self.run_line(&command);
+        self.in_trap = false;
+        self.status = status;
+    }
+
+    /// Run the traps associated to the signals received since the last call.
+    fn run_pending_traps(&mut self) {
+        let pending = PENDING_SIGNALS.swap(0, Ordering::SeqCst);
+        if pending == 0 {
+            return;
+        }
+
+        for (name, number) in SIGNALS.iter() {
+            if pending & (1 << number) != 0 {
+                self.run_trap(name);
+            }
+        }
+    }
+
+    fn run_err_trap(&mut self) {
+        self.run_trap("ERR");
+    }
+
+    /// Run the `EXIT` trap. It is unregistered afterwards so it can only run once.
+    fn run_exit_trap(&mut self) {
+        self.run_trap("EXIT");
+        self.traps.remove("EXIT");
+    }
+}
@@ -7,6 +7,8 @@ use std::io::Read;
 
 pub trait Utils {
     fn perform_expansion_on_single_element(value: &str) -> String;
+    fn perform_variable_expansion(value: &str, lookup: &dyn Fn(&str) -> Vec<String>)
+        -> Vec<String>;
     fn perform_wildcard_expansion(value: &str) -> Option<Vec<String>>;
     fn default_prompt() -> String;
     fn get_username() -> String;
@@ -24,17 +26,72 @@ impl Utils for Osh {
             return value.into();
         }
 
-        // Replace environment variable
-        let mut result = String::new();
-        if let Some(key) = value.strip_prefix('$') {
-            // Lookup for the given value in the environment
-            result = match env::var(key) {
-                Ok(x) => x,
-                Err(_) => "".into(),
-            };
+        // Replace environment variables
+        Osh::perform_variable_expansion(value, &|name| env::var(name).into_iter().collect())
+            .join(" ")
+    }
+
+    /// Replace every `$name` and `${name}` found in `value` with the values returned by
+    /// `lookup`. A parameter expanding to several values, such as `$@`, produces several words,
+    /// the text surrounding the parameter being attached to the first and last ones.
+    fn perform_variable_expansion(
+        value: &str,
+        lookup: &dyn Fn(&str) -> Vec<String>,
+    ) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut current = String::new();
+        let mut chars = value.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                current.push(c);
+                continue;
+            }
+
+            let mut name = String::new();
+            match chars.peek() {
+                Some('{') => {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                        name.push(c);
+                    }
+                }
+                // Special parameters and positional parameters are a single character long
+                Some(&c) if "?$!#*@-".contains(c) || c.is_ascii_digit() => {
+                    chars.next();
+                    name.push(c);
+                }
+                Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+                    while let Some(&c) = chars.peek() {
+                        if !c.is_ascii_alphanumeric() && c != '_' {
+                            break;
+                        }
+                        name.push(c);
+                        chars.next();
+                    }
+                }
+                // A lone `$` is kept as is
+                _ => {
+                    current.push('$');
+                    continue;
+                }
+            }
+
+            let mut values = lookup(&name).into_iter();
+            if let Some(first) = values.next() {
+                current += &first;
+            }
+            for value in values {
+                words.push(current);
+                current = value;
+            }
         }
+        words.push(current);
 
-        result
+        words
     }
 
     fn perform_wildcard_expansion(value: &str) -> Option<Vec<String>> {